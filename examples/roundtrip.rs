@@ -0,0 +1,24 @@
+// Encode a payload with an image and decode it again, entirely in memory.
+// Run with: cargo run --example roundtrip
+use metastego::{EncodeOptions, DecodeOptions, encode_bytes, decode_bytes};
+
+fn main() {
+	// Any buffer that contains all 256 byte values can serve as the image. Here, every value twice over, in a scrambled order.
+	let image : Vec<u8> = (0..512u32).map(|x| (x.wrapping_mul(167) % 256) as u8).collect();
+	let payload = b"Meet me at the usual place at midnight.";
+
+	// Record a checksum, so decoding with the wrong image fails instead of producing garbage.
+	let options = EncodeOptions { checksum: true, ..EncodeOptions::default() };
+	let container = match encode_bytes(payload, &image, &options) {
+		Ok(x) => x,
+		Err(e) => panic!("Failed to encode: {}", e)
+	};
+	println!("Encoded {} payload bytes into a {}-byte container", payload.len(), container.len());
+
+	let decoded = match decode_bytes(&container, &image, &DecodeOptions::default()) {
+		Ok(x) => x,
+		Err(e) => panic!("Failed to decode: {}", e)
+	};
+	assert_eq!(decoded, payload);
+	println!("Decoded: {}", String::from_utf8_lossy(&decoded));
+}
@@ -0,0 +1,68 @@
+// Packing several named payloads into one, for containers that carry a multi-payload archive.
+// Each entry is stored as a big-endian u16 name length, the UTF-8 name, a big-endian u64 data length,
+// the SHA-256 of the data, and then the data itself.
+use crate::{MetastegError, sha256};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+	pub name: String,
+	pub data: Vec<u8>
+}
+
+// Pack entries into a single payload, ready to be encoded.
+pub fn pack(entries: &[Entry]) -> Result<Vec<u8>,MetastegError> {
+	let mut packed : Vec<u8> = Vec::new();
+	for entry in entries {
+		if !is_safe_name(&entry.name) {
+			return Err(MetastegError::InvalidArchive(format!("entry name '{}' is not a plain file name", entry.name)));
+		}
+		packed.extend_from_slice(&(entry.name.len() as u16).to_be_bytes());
+		packed.extend_from_slice(entry.name.as_bytes());
+		packed.extend_from_slice(&(entry.data.len() as u64).to_be_bytes());
+		packed.extend_from_slice(&sha256(&entry.data));
+		packed.extend_from_slice(&entry.data);
+	}
+	Ok(packed)
+}
+
+// Unpack a decoded payload into its entries, along with the checksum each was verified against.
+pub fn unpack(packed: &[u8]) -> Result<Vec<(Entry, [u8;32])>,MetastegError> {
+	let mut entries : Vec<(Entry, [u8;32])> = Vec::new();
+	let mut rest = packed;
+	while !rest.is_empty() {
+		let name_length = u16::from_be_bytes(take(&mut rest, 2)?.try_into().unwrap()) as usize;
+		let name = match String::from_utf8(take(&mut rest, name_length)?.to_vec()) {
+			Ok(x) => x,
+			Err(_) => return Err(MetastegError::InvalidArchive(format!("name of entry {} is not valid UTF-8", entries.len() + 1)))
+		};
+		// Names become paths when extracting, so anything that could escape the output directory is rejected.
+		if !is_safe_name(&name) {
+			return Err(MetastegError::InvalidArchive(format!("entry name '{}' is not a plain file name", name)));
+		}
+		let data_length = u64::from_be_bytes(take(&mut rest, 8)?.try_into().unwrap());
+		let checksum : [u8;32] = take(&mut rest, 32)?.try_into().unwrap();
+		let data = match usize::try_from(data_length) {
+			Ok(x) => take(&mut rest, x)?.to_vec(),
+			Err(_) => return Err(MetastegError::InvalidArchive(format!("entry '{}' is too large", name)))
+		};
+		if sha256(&data) != checksum {
+			return Err(MetastegError::InvalidArchive(format!("entry '{}' does not match its checksum", name)));
+		}
+		entries.push((Entry { name, data }, checksum));
+	}
+	Ok(entries)
+}
+
+// Whether a name is a single, plain path component that is safe to create inside a directory.
+pub fn is_safe_name(name: &str) -> bool {
+	!name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\', '\0']) && !name.contains(':')
+}
+
+fn take<'a>(rest: &mut &'a [u8], length: usize) -> Result<&'a [u8],MetastegError> {
+	if rest.len() < length {
+		return Err(MetastegError::InvalidArchive("archive is truncated".to_string()));
+	}
+	let (taken, remaining) = rest.split_at(length);
+	*rest = remaining;
+	Ok(taken)
+}
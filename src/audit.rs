@@ -0,0 +1,161 @@
+// Audit manifests: a JSON record of what went into an encode and what came out, optionally signed with Ed25519.
+// The manifest holds only hashes and settings, never the payload or image themselves, and no timestamps, so the same encode always gives the same manifest.
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde_json::{json, Value};
+
+use crate::{MetastegError, sha256};
+use crate::codec::Format;
+use crate::header::parse_header;
+
+// The manifest of one encode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+	// The version of metastego that wrote the manifest.
+	pub tool_version: String,
+	pub payload_sha256: [u8;32],
+	pub image_sha256: [u8;32],
+	pub container_sha256: [u8;32],
+	// The settings recorded in the container's header.
+	pub width: u8,
+	pub flags: u32,
+	pub format: String,
+	// The Ed25519 public key and signature over the rest of the manifest, if it was signed.
+	pub signature: Option<([u8;32], [u8;64])>
+}
+
+impl Manifest {
+	// Describe an encode from its payload, image and the container it produced.
+	pub fn new(payload: &[u8], image: &[u8], container: &[u8]) -> Result<Manifest,MetastegError> {
+		let (header, _) = parse_header(container)?;
+		Ok(Manifest {
+			tool_version: env!("CARGO_PKG_VERSION").to_string(),
+			payload_sha256: sha256(payload),
+			image_sha256: sha256(image),
+			container_sha256: sha256(container),
+			width: header.width,
+			flags: header.flags,
+			format: Format::from_flags(header.flags).name().to_string(),
+			signature: None
+		})
+	}
+
+	// The manifest without its signature, as the bytes a signature covers.
+	// serde_json sorts object keys, so the same manifest always gives the same bytes.
+	fn signed_bytes(&self) -> Vec<u8> {
+		self.unsigned_value().to_string().into_bytes()
+	}
+
+	fn unsigned_value(&self) -> Value {
+		json!({
+			"tool_version": self.tool_version,
+			"payload_sha256": hex(&self.payload_sha256),
+			"image_sha256": hex(&self.image_sha256),
+			"container_sha256": hex(&self.container_sha256),
+			"width": self.width,
+			"flags": self.flags,
+			"format": self.format
+		})
+	}
+
+	// Sign the manifest with an Ed25519 secret key, replacing any earlier signature.
+	pub fn sign(&mut self, secret_key: &[u8;32]) {
+		let key = SigningKey::from_bytes(secret_key);
+		let signature = key.sign(&self.signed_bytes());
+		self.signature = Some((key.verifying_key().to_bytes(), signature.to_bytes()));
+	}
+
+	// Serialize the manifest as pretty-printed JSON.
+	pub fn to_json(&self) -> String {
+		let mut value = self.unsigned_value();
+		if let Some((public_key, signature)) = &self.signature {
+			value["public_key"] = json!(hex(public_key));
+			value["signature"] = json!(hex(signature));
+		}
+		let mut json = serde_json::to_string_pretty(&value).unwrap();
+		json.push('\n');
+		json
+	}
+
+	// Parse a manifest written by to_json.
+	pub fn parse(json: &str) -> Result<Manifest,MetastegError> {
+		let value : Value = match serde_json::from_str(json) {
+			Ok(x) => x,
+			Err(e) => return Err(MetastegError::InvalidManifest(e.to_string()))
+		};
+		let text = |name: &str| match value[name].as_str() {
+			Some(x) => Ok(x.to_string()),
+			None => Err(MetastegError::InvalidManifest(format!("'{}' is missing or not a string", name)))
+		};
+		let number = |name: &str| match value[name].as_u64() {
+			Some(x) => Ok(x),
+			None => Err(MetastegError::InvalidManifest(format!("'{}' is missing or not a number", name)))
+		};
+		let digest = |name: &str| parse_hex::<32>(&text(name)?).ok_or_else(|| MetastegError::InvalidManifest(format!("'{}' is not a SHA-256 hash", name)));
+		let signature = match (value.get("public_key"), value.get("signature")) {
+			(None, None) => None,
+			(Some(_), Some(_)) => match (parse_hex::<32>(&text("public_key")?), parse_hex::<64>(&text("signature")?)) {
+				(Some(public_key), Some(signature)) => Some((public_key, signature)),
+				_ => return Err(MetastegError::InvalidManifest("the public key or signature is malformed".to_string()))
+			},
+			_ => return Err(MetastegError::InvalidManifest("a signature needs both 'public_key' and 'signature'".to_string()))
+		};
+		Ok(Manifest {
+			tool_version: text("tool_version")?,
+			payload_sha256: digest("payload_sha256")?,
+			image_sha256: digest("image_sha256")?,
+			container_sha256: digest("container_sha256")?,
+			width: u8::try_from(number("width")?).map_err(|_| MetastegError::InvalidManifest("'width' is out of range".to_string()))?,
+			flags: u32::try_from(number("flags")?).map_err(|_| MetastegError::InvalidManifest("'flags' is out of range".to_string()))?,
+			format: text("format")?,
+			signature
+		})
+	}
+
+	// Check the signature, if there is one. If a public key is given, the manifest must be signed with it.
+	pub fn verify_signature(&self, public_key: Option<&[u8;32]>) -> Result<(),MetastegError> {
+		let (signer, signature) = match (&self.signature, public_key) {
+			(Some(x), _) => x,
+			(None, Some(_)) => return Err(MetastegError::AuditMismatch("the manifest isn't signed".to_string())),
+			(None, None) => return Ok(())
+		};
+		if public_key.is_some_and(|key| key != signer) {
+			return Err(MetastegError::AuditMismatch("the manifest is signed with a different key".to_string()));
+		}
+		let key = VerifyingKey::from_bytes(signer).map_err(|_| MetastegError::AuditMismatch("the public key is invalid".to_string()))?;
+		key.verify(&self.signed_bytes(), &Signature::from_bytes(signature)).map_err(|_| MetastegError::AuditMismatch("the signature doesn't match the manifest".to_string()))
+	}
+
+	// Check the manifest describes these files, and that its signature is good.
+	// The tool version isn't compared, so a manifest can be checked by a later version.
+	pub fn verify(&self, payload: &[u8], image: &[u8], container: &[u8], public_key: Option<&[u8;32]>) -> Result<(),MetastegError> {
+		self.verify_signature(public_key)?;
+		let actual = Manifest::new(payload, image, container)?;
+		let checks = [
+			("payload", self.payload_sha256 == actual.payload_sha256),
+			("image", self.image_sha256 == actual.image_sha256),
+			("container", self.container_sha256 == actual.container_sha256),
+			("container settings", (self.width, self.flags, &self.format) == (actual.width, actual.flags, &actual.format))
+		];
+		match checks.iter().find(|(_, ok)| !ok) {
+			Some((name, _)) => Err(MetastegError::AuditMismatch(format!("the {} doesn't match the manifest", name))),
+			None => Ok(())
+		}
+	}
+}
+
+// Parse a key or signature of a fixed length from hex, ignoring surrounding whitespace.
+pub fn parse_hex<const N: usize>(text: &str) -> Option<[u8;N]> {
+	let text = text.trim();
+	if text.len() != N * 2 || !text.is_ascii() {
+		return None;
+	}
+	let mut bytes = [0u8;N];
+	for (i, byte) in bytes.iter_mut().enumerate() {
+		*byte = u8::from_str_radix(&text[i * 2..i * 2 + 2], 16).ok()?;
+	}
+	Some(bytes)
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+	bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
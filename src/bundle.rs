@@ -0,0 +1,30 @@
+// Bundles: the image and a container in one file, for sharing when the image doesn't need to be kept secret.
+// A bundle is the image, then the container, then a trailer of the container's length as a big-endian u64 and the bundle magic.
+// The trailer is at the very end, so it can be found without knowing anything about the image.
+use crate::MetastegError;
+
+pub const BUNDLE_MAGIC : &[u8;8] = b"MSTGBNDL";
+const TRAILER_LEN : usize = 16;
+
+// Put an image and a container together in a bundle.
+pub fn bundle(image: &[u8], container: &[u8]) -> Vec<u8> {
+	let mut bundled : Vec<u8> = Vec::with_capacity(image.len() + container.len() + TRAILER_LEN);
+	bundled.extend_from_slice(image);
+	bundled.extend_from_slice(container);
+	bundled.extend_from_slice(&(container.len() as u64).to_be_bytes());
+	bundled.extend_from_slice(BUNDLE_MAGIC);
+	bundled
+}
+
+// Split a bundle into its image and container.
+pub fn unbundle(bundled: &[u8]) -> Result<(&[u8], &[u8]),MetastegError> {
+	if bundled.len() < TRAILER_LEN || !bundled.ends_with(BUNDLE_MAGIC) {
+		return Err(MetastegError::InvalidBundle("there is no bundle trailer at the end of the file".to_string()));
+	}
+	let body = &bundled[..bundled.len() - TRAILER_LEN];
+	let length = u64::from_be_bytes(bundled[body.len()..body.len() + 8].try_into().unwrap());
+	match usize::try_from(length) {
+		Ok(x) if x <= body.len() => Ok(body.split_at(body.len() - x)),
+		_ => Err(MetastegError::InvalidBundle(format!("the trailer declares a {}-byte container, but the bundle only has {} bytes before it", length, body.len())))
+	}
+}
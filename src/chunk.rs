@@ -0,0 +1,82 @@
+// Content-defined chunking of a payload, so chunk boundaries depend on the bytes around them instead of their position.
+// Nothing here needs std, so it is available without the std feature.
+// It is a FastCDC-style gear hash: a boundary falls wherever the hash of the last 64 bytes has its top bits clear.
+// An edit only moves the boundaries near it, so the chunks before and after it come out the same.
+
+// The smallest average chunk size that can be asked for.
+pub const MIN_AVERAGE : usize = 64;
+
+// A fixed table of random values, one for each byte value, generated with splitmix64 so it never changes.
+const GEAR : [u64;256] = gear_table();
+
+const fn gear_table() -> [u64;256] {
+	let mut table = [0u64;256];
+	let mut state : u64 = 0x6d65_7461_7374_6567;
+	let mut i = 0;
+	while i < 256 {
+		state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+		let mut z = state;
+		z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+		table[i] = z ^ (z >> 31);
+		i += 1;
+	}
+	table
+}
+
+// A mask over the top bits of the hash, which a boundary needs to be clear.
+fn top_bits(bits: u32) -> u64 {
+	!0u64 << (64 - bits)
+}
+
+// The smallest and largest chunk a given average allows. Only the last chunk of a buffer can be smaller.
+pub fn chunk_bounds(average: usize) -> (usize, usize) {
+	(average / 4, average.saturating_mul(4))
+}
+
+// Find the length of the first chunk in a buffer, for chunks of roughly the given average size (at least MIN_AVERAGE).
+// Chunks are cut short of the average less often, and past it more often, so their sizes bunch up around the average.
+// If the buffer ends before a boundary is found, the whole buffer is the chunk.
+pub fn cut_point(data: &[u8], average: usize) -> usize {
+	let average = average.max(MIN_AVERAGE);
+	let (min, max) = chunk_bounds(average);
+	if data.len() <= min {
+		return data.len();
+	}
+	let bits = average.ilog2();
+	let (mask_short, mask_long) = (top_bits(bits + 1), top_bits(bits - 1));
+	let end = data.len().min(max);
+	let mut hash : u64 = 0;
+	for (i, byte) in data.iter().enumerate().take(end).skip(min) {
+		hash = (hash << 1).wrapping_add(GEAR[*byte as usize]);
+		let mask = if i < average { mask_short } else { mask_long };
+		if hash & mask == 0 {
+			return i + 1;
+		}
+	}
+	end
+}
+
+// Split a buffer into content-defined chunks of roughly the given average size.
+pub fn chunks(data: &[u8], average: usize) -> Chunks<'_> {
+	Chunks { data, average }
+}
+
+// An iterator over the content-defined chunks of a buffer.
+pub struct Chunks<'a> {
+	data: &'a [u8],
+	average: usize
+}
+
+impl<'a> Iterator for Chunks<'a> {
+	type Item = &'a [u8];
+
+	fn next(&mut self) -> Option<&'a [u8]> {
+		if self.data.is_empty() {
+			return None;
+		}
+		let (chunk, rest) = self.data.split_at(cut_point(self.data, self.average));
+		self.data = rest;
+		Some(chunk)
+	}
+}
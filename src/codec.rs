@@ -0,0 +1,283 @@
+// Formats the offsets after the header can be serialized in.
+use std::collections::BTreeSet;
+use std::io::Write;
+
+use crate::{MetastegError, check_length};
+use crate::header::{Header, FLAG_VARINT_OFFSETS, FLAG_PLANAR_OFFSETS, FLAG_DICTIONARY_OFFSETS};
+use crate::oracle::Offsets;
+
+impl Offsets {
+	// Serialize the offsets as big-endian integers of the given width.
+	pub fn serialize(&self, width: u8) -> Result<Vec<u8>,MetastegError> {
+		FixedWidth(width).serialize_to_vec(self)
+	}
+
+	// Deserialize big-endian offsets of the given width, checking the bytes hold a whole number of them.
+	pub fn deserialize(bytes: &[u8], width: u8) -> Result<Offsets,MetastegError> {
+		Ok(Offsets::from(FixedWidth(width).deserialize(bytes)?))
+	}
+}
+
+// Converts offsets to and from the bytes stored in a container.
+pub trait OffsetCodec {
+	fn serialize(&self, offsets: &[u32], out: &mut impl Write) -> Result<(),MetastegError>;
+	fn deserialize(&self, bytes: &[u8]) -> Result<Vec<u32>,MetastegError>;
+
+	fn serialize_to_vec(&self, offsets: &[u32]) -> Result<Vec<u8>,MetastegError> {
+		let mut serialized : Vec<u8> = Vec::new();
+		self.serialize(offsets, &mut serialized)?;
+		Ok(serialized)
+	}
+}
+
+// Big-endian integers of the width declared in the header.
+pub struct FixedWidth(pub u8);
+
+// LEB128 varints: 7 bits per byte, least significant group first, with the top bit set on every byte but the last.
+// Small offsets take a single byte, so this suits images where the oracle offsets are mostly near the start.
+pub struct Varint;
+
+// Big-endian integers of the width declared in the header, stored one byte plane at a time:
+// the most significant byte of every offset, then the next byte of every offset, and so on.
+// Grouping bytes of similar magnitude like this makes the offsets compress much better.
+pub struct Planar(pub u8);
+
+// A dictionary of the distinct offsets, followed by a one-byte index into it for each offset.
+// The dictionary is a big-endian u16 count and then the offsets as big-endian integers of the declared width, in ascending order.
+// Payloads with a small alphabet only use a few distinct offsets, so this stores each one in a single byte whatever the width.
+pub struct Dictionary(pub u8);
+
+// The most distinct offsets a dictionary can hold, so that every index fits in a byte.
+pub const MAX_DICTIONARY_SIZE : usize = 256;
+
+// The serialization formats that can be selected when encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+	#[default]
+	Fixed,
+	Varint,
+	Planar,
+	Dictionary
+}
+
+impl Format {
+	pub fn from_name(name: &str) -> Option<Format> {
+		match name {
+			"fixed" => Some(Format::Fixed),
+			"varint" => Some(Format::Varint),
+			"planar" => Some(Format::Planar),
+			"dictionary" => Some(Format::Dictionary),
+			_ => None
+		}
+	}
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			Format::Fixed => "fixed",
+			Format::Varint => "varint",
+			Format::Planar => "planar",
+			Format::Dictionary => "dictionary"
+		}
+	}
+
+	// The header flag that selects this format, if it isn't the default.
+	pub fn flag(&self) -> u32 {
+		match self {
+			Format::Fixed => 0,
+			Format::Varint => FLAG_VARINT_OFFSETS,
+			Format::Planar => FLAG_PLANAR_OFFSETS,
+			Format::Dictionary => FLAG_DICTIONARY_OFFSETS
+		}
+	}
+
+	pub fn from_flags(flags: u32) -> Format {
+		if flags & FLAG_VARINT_OFFSETS != 0 {
+			Format::Varint
+		} else if flags & FLAG_PLANAR_OFFSETS != 0 {
+			Format::Planar
+		} else if flags & FLAG_DICTIONARY_OFFSETS != 0 {
+			Format::Dictionary
+		} else {
+			Format::Fixed
+		}
+	}
+}
+
+// The codec for whichever format a header declares.
+pub enum Codec {
+	Fixed(FixedWidth),
+	Varint(Varint),
+	Planar(Planar),
+	Dictionary(Dictionary)
+}
+
+pub fn codec_for(header: &Header) -> Codec {
+	match Format::from_flags(header.flags) {
+		Format::Fixed => Codec::Fixed(FixedWidth(header.width)),
+		Format::Varint => Codec::Varint(Varint),
+		Format::Planar => Codec::Planar(Planar(header.width)),
+		Format::Dictionary => Codec::Dictionary(Dictionary(header.width))
+	}
+}
+
+impl OffsetCodec for Codec {
+	fn serialize(&self, offsets: &[u32], out: &mut impl Write) -> Result<(),MetastegError> {
+		match self {
+			Codec::Fixed(x) => x.serialize(offsets, out),
+			Codec::Varint(x) => x.serialize(offsets, out),
+			Codec::Planar(x) => x.serialize(offsets, out),
+			Codec::Dictionary(x) => x.serialize(offsets, out)
+		}
+	}
+
+	fn deserialize(&self, bytes: &[u8]) -> Result<Vec<u32>,MetastegError> {
+		match self {
+			Codec::Fixed(x) => x.deserialize(bytes),
+			Codec::Varint(x) => x.deserialize(bytes),
+			Codec::Planar(x) => x.deserialize(bytes),
+			Codec::Dictionary(x) => x.deserialize(bytes)
+		}
+	}
+}
+
+impl OffsetCodec for FixedWidth {
+	// If an offset is too large for the width, it will return an error naming the offset.
+	fn serialize(&self, offsets: &[u32], out: &mut impl Write) -> Result<(),MetastegError> {
+		let width = self.0;
+		for offset in offsets {
+			let offset_bytes = (*offset as u64).to_be_bytes();
+			let (high, low) = offset_bytes.split_at(8 - width as usize);
+			if high.iter().any(|x| *x != 0) {
+				return Err(MetastegError::OffsetTooWide { offset: *offset, width });
+			}
+			out.write_all(low)?;
+		}
+		Ok(())
+	}
+
+	fn deserialize(&self, bytes: &[u8]) -> Result<Vec<u32>,MetastegError> {
+		let width = self.0;
+		check_length(bytes.len(), width)?;
+		// The number of offsets comes from the length of the body actually present, never from anything the header claims.
+		let mut offsets : Vec<u32> = Vec::with_capacity(bytes.len() / width as usize);
+		for current_offset_serialized in bytes.chunks_exact(width as usize) {
+			let mut offset_bytes = [0u8;8];
+			offset_bytes[8 - width as usize..].copy_from_slice(current_offset_serialized);
+			let offset = u64::from_be_bytes(offset_bytes);
+			match u32::try_from(offset) {
+				Ok(x) => offsets.push(x),
+				Err(_) => return Err(MetastegError::OffsetOutOfBounds(offset))
+			}
+		}
+		Ok(offsets)
+	}
+}
+
+impl OffsetCodec for Varint {
+	fn serialize(&self, offsets: &[u32], out: &mut impl Write) -> Result<(),MetastegError> {
+		for offset in offsets {
+			let mut value = *offset;
+			while value >= 0x80 {
+				out.write_all(&[(value as u8 & 0x7f) | 0x80])?;
+				value >>= 7;
+			}
+			out.write_all(&[value as u8])?;
+		}
+		Ok(())
+	}
+
+	fn deserialize(&self, bytes: &[u8]) -> Result<Vec<u32>,MetastegError> {
+		// Every varint takes at least one byte, so the body can't hold more offsets than it has bytes.
+		let mut offsets : Vec<u32> = Vec::with_capacity(bytes.len());
+		let mut value : u64 = 0;
+		let mut shift = 0;
+		for byte in bytes {
+			if shift > 28 {
+				return Err(MetastegError::InvalidOffsets(format!("varint for offset {} is longer than 5 bytes", offsets.len())));
+			}
+			value |= ((byte & 0x7f) as u64) << shift;
+			if byte & 0x80 != 0 {
+				shift += 7;
+				continue;
+			}
+			match u32::try_from(value) {
+				Ok(x) => offsets.push(x),
+				Err(_) => return Err(MetastegError::OffsetOutOfBounds(value))
+			}
+			value = 0;
+			shift = 0;
+		}
+		if shift != 0 {
+			return Err(MetastegError::InvalidOffsets("last varint is truncated".to_string()));
+		}
+		Ok(offsets)
+	}
+}
+
+impl OffsetCodec for Planar {
+	fn serialize(&self, offsets: &[u32], out: &mut impl Write) -> Result<(),MetastegError> {
+		// Serialize as fixed-width first, so offsets that don't fit are reported the same way.
+		let width = self.0 as usize;
+		let interleaved = FixedWidth(self.0).serialize_to_vec(offsets)?;
+		for plane in 0..width {
+			let bytes : Vec<u8> = interleaved.iter().skip(plane).step_by(width).copied().collect();
+			out.write_all(&bytes)?;
+		}
+		Ok(())
+	}
+
+	fn deserialize(&self, bytes: &[u8]) -> Result<Vec<u32>,MetastegError> {
+		let width = self.0 as usize;
+		check_length(bytes.len(), self.0)?;
+		let count = bytes.len() / width;
+		let mut interleaved = vec![0u8; bytes.len()];
+		for (plane, plane_bytes) in bytes.chunks_exact(count.max(1)).enumerate() {
+			for (i, byte) in plane_bytes.iter().enumerate() {
+				interleaved[i * width + plane] = *byte;
+			}
+		}
+		FixedWidth(self.0).deserialize(&interleaved)
+	}
+}
+
+impl OffsetCodec for Dictionary {
+	// If there are more distinct offsets than a dictionary can hold, it will return an error.
+	fn serialize(&self, offsets: &[u32], out: &mut impl Write) -> Result<(),MetastegError> {
+		let dictionary : Vec<u32> = offsets.iter().copied().collect::<BTreeSet<u32>>().into_iter().collect();
+		if dictionary.len() > MAX_DICTIONARY_SIZE {
+			return Err(MetastegError::UnsupportedFeature(format!("{} distinct offsets are too many for a dictionary, which holds at most {}", dictionary.len(), MAX_DICTIONARY_SIZE)));
+		}
+		out.write_all(&(dictionary.len() as u16).to_be_bytes())?;
+		FixedWidth(self.0).serialize(&dictionary, out)?;
+		// The dictionary is sorted, so each offset's index can be found by binary search.
+		let indices : Vec<u8> = offsets.iter().map(|offset| dictionary.binary_search(offset).unwrap() as u8).collect();
+		out.write_all(&indices)?;
+		Ok(())
+	}
+
+	fn deserialize(&self, bytes: &[u8]) -> Result<Vec<u32>,MetastegError> {
+		if bytes.is_empty() {
+			return Ok(Vec::new());
+		}
+		if bytes.len() < 2 {
+			return Err(MetastegError::InvalidOffsets("dictionary size is truncated".to_string()));
+		}
+		let count = u16::from_be_bytes([bytes[0], bytes[1]]) as usize;
+		if count > MAX_DICTIONARY_SIZE {
+			return Err(MetastegError::InvalidOffsets(format!("dictionary declares {} offsets, but holds at most {}", count, MAX_DICTIONARY_SIZE)));
+		}
+		let end = 2 + count * self.0 as usize;
+		if bytes.len() < end {
+			return Err(MetastegError::InvalidOffsets("dictionary is truncated".to_string()));
+		}
+		let dictionary = FixedWidth(self.0).deserialize(&bytes[2..end])?;
+		let mut offsets : Vec<u32> = Vec::with_capacity(bytes.len() - end);
+		for (i, index) in bytes[end..].iter().enumerate() {
+			match dictionary.get(*index as usize) {
+				Some(x) => offsets.push(*x),
+				None => return Err(MetastegError::InvalidOffsets(format!("offset {} refers to entry {} of a {}-entry dictionary", i, index, count)))
+			}
+		}
+		Ok(offsets)
+	}
+}
@@ -0,0 +1,23 @@
+// Deflate compression, used for the payload before encoding and for the serialized offsets after encoding.
+use std::io::{Read, Write};
+
+use flate2::Compression;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+
+use crate::MetastegError;
+
+pub fn deflate(buf: &[u8]) -> Vec<u8> {
+	let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+	// Writing to a Vec can't fail.
+	encoder.write_all(buf).unwrap();
+	encoder.finish().unwrap()
+}
+
+pub fn inflate(buf: &[u8]) -> Result<Vec<u8>,MetastegError> {
+	let mut inflated : Vec<u8> = Vec::new();
+	match DeflateDecoder::new(buf).read_to_end(&mut inflated) {
+		Ok(_) => Ok(inflated),
+		Err(e) => Err(MetastegError::InvalidCompression(e.to_string()))
+	}
+}
@@ -0,0 +1,602 @@
+// Encoding and decoding whole containers: the options, the header checks, and the offset translation in between.
+use std::collections::BTreeSet;
+use std::io::{self, Read};
+
+use sha2::{Digest, Sha256};
+
+use crate::{MetastegError, Header, Encoder, Format, OffsetCodec, WIDTHS};
+use crate::{codec, compress, disguise, header, oracle, permute, region, rng, sliding, transform};
+use crate::oracle::Offsets;
+
+// How much of a payload encode_reader reads at a time.
+const READ_CHUNK_SIZE : usize = 64 * 1024;
+
+// How many occurrences of each byte value a seeded encode keeps to pick from by default: at most a million offsets in all.
+pub const DEFAULT_OCCURRENCE_LIMIT : usize = 4096;
+
+// Options that affect how a payload is encoded.
+#[derive(Debug, Clone)]
+pub struct EncodeOptions {
+	// Only use offsets below this value in the image.
+	pub max_offset: Option<u32>,
+	// The number of bytes used to serialize each offset.
+	pub width: u8,
+	// Scramble the order of the offsets with a permutation derived from this key.
+	pub permute_key: Option<String>,
+	// Write a fake file-type preamble in front of the container.
+	pub disguise: Option<disguise::Disguise>,
+	// Record a checksum of the payload, so decoding with the wrong image is detected.
+	pub checksum: bool,
+	// Record a fingerprint of the image, so the right image can be identified without decoding.
+	// Note that this lets anyone holding candidate images confirm which one was used.
+	pub fingerprint: bool,
+	// Store bytes missing from the image as a sentinel offset followed by their literal value, instead of failing.
+	pub escape: bool,
+	// Only encode the part of the payload starting at this offset.
+	pub payload_offset: Option<u64>,
+	// Only encode this many bytes of the payload.
+	pub payload_length: Option<u64>,
+	// Deflate the payload before encoding it, so fewer offsets are needed.
+	pub compress_payload: bool,
+	// Deflate the serialized offsets after encoding.
+	pub compress_offsets: bool,
+	// The format the offsets are serialized in.
+	pub format: Format,
+	// Record the hash and length of each image, so decode can check it was given the right images in the right order.
+	pub image_list: bool,
+	// Authenticate the container with an HMAC-SHA256 under this key.
+	pub mac_key: Option<String>,
+	// Only use offsets whose serialization contains none of these bytes.
+	pub avoid_bytes: Vec<u8>,
+	// Record this MIME type for the payload in the header.
+	pub content_type: Option<String>,
+	// Guess the payload's MIME type from its contents and record it, if there's no content type already.
+	pub sniff_content_type: bool,
+	// Restrict byte values to regions of the image.
+	pub regions: Vec<region::Region>,
+	// The payload is an archive packed with archive::pack, so decode should unpack it into separate files.
+	pub archive: bool,
+	// Spread the offsets by picking a random occurrence of each payload byte, seeded from this value, instead of always the first.
+	pub seed: Option<String>,
+	// The generator used to make those picks.
+	pub rng: rng::RngAlgorithm,
+	// With a seed, pick one offset per byte value for each block of this many payload bytes, rather than a new one for every byte.
+	pub block_size: Option<usize>,
+	// With a seed, pick one offset per byte value for each content-defined chunk of roughly this many payload bytes, instead of fixed-size blocks.
+	pub chunk_average: Option<usize>,
+	// Choose offsets into the image as it will be after this transform, rather than the image as given.
+	pub transform: Option<transform::Transform>,
+	// Store each offset relative to a window that slides through the image as the payload is encoded, rather than from the start of the image.
+	pub sliding_window: Option<sliding::SlidingWindow>,
+	// Encode each byte as its first occurrence after the offset before it, so the offsets strictly increase.
+	pub monotonic: bool,
+	// Record the length of the image, so strict decoding can check the image given against it.
+	pub record_image_length: bool,
+	// With a seed, keep at most this many occurrences of each byte value to pick from, sampled at random, to bound memory use.
+	pub limit_occurrences: Option<usize>,
+	// Refuse to encode a payload byte value that occurs fewer than this many times at offsets the options allow, so no byte is forced onto one offset.
+	pub min_occurrences: Option<u64>
+}
+
+impl Default for EncodeOptions {
+	fn default() -> EncodeOptions {
+		EncodeOptions { max_offset: None, width: 4, permute_key: None, disguise: None, checksum: false, fingerprint: false, escape: false, payload_offset: None, payload_length: None, compress_payload: false, compress_offsets: false, format: Format::Fixed, image_list: false, mac_key: None, avoid_bytes: Vec::new(), content_type: None, sniff_content_type: false, regions: Vec::new(), archive: false, seed: None, rng: rng::RngAlgorithm::ChaCha20, block_size: None, chunk_average: None, transform: None, sliding_window: None, monotonic: false, record_image_length: false, limit_occurrences: Some(DEFAULT_OCCURRENCE_LIMIT), min_occurrences: None }
+	}
+}
+
+// Options that affect how a container is decoded.
+#[derive(Debug, Clone, Default)]
+pub struct DecodeOptions {
+	// The key used to permute the offsets, for containers encoded with one.
+	pub permute_key: Option<String>,
+	// Reject containers whose header declares a different offset width, rather than trusting the header.
+	pub expect_width: Option<u8>,
+	// The key used to check the container's MAC. If it is given, containers without a MAC are rejected.
+	pub mac_key: Option<String>,
+	// Check the image given has the length recorded in the header, and that every offset lies inside it.
+	// Containers that don't record the image length are rejected.
+	pub strict_offsets: bool
+}
+
+// The SHA-256 digest used for payload checksums and image fingerprints.
+pub fn sha256(buf: &[u8]) -> [u8;32] {
+	Sha256::digest(buf).into()
+}
+
+// Guess the MIME type of a payload from its leading bytes. Returns None if it isn't a type that can be recognised.
+pub fn sniff_content_type(payload: &[u8]) -> Option<String> {
+	infer::get(payload).map(|kind| kind.mime_type().to_string())
+}
+
+// Suggest a file extension (without the dot) for a payload, from its MIME type if one was recorded, or else by sniffing it.
+// Returns None if neither gives a type with a known extension.
+pub fn extension_for(content_type: Option<&str>, payload: &[u8]) -> Option<String> {
+	let sniffed = infer::get(payload);
+	let content_type = match content_type {
+		Some(x) => x,
+		None => return sniffed.map(|kind| kind.extension().to_string())
+	};
+	if let Some(kind) = sniffed.filter(|kind| kind.mime_type() == content_type) {
+		return Some(kind.extension().to_string());
+	}
+	// Sniffing only recognises binary formats, so common text types are looked up here.
+	let extension = match content_type {
+		"text/plain" => "txt",
+		"text/html" => "html",
+		"text/css" => "css",
+		"text/csv" => "csv",
+		"text/markdown" => "md",
+		"text/xml" | "application/xml" => "xml",
+		"application/json" => "json",
+		"application/javascript" | "text/javascript" => "js",
+		_ => return None
+	};
+	Some(extension.to_string())
+}
+
+// HMAC-SHA256 (RFC 2104) of the concatenation of some buffers.
+pub fn hmac_sha256(key: &[u8], parts: &[&[u8]]) -> [u8;32] {
+	let mut block = [0u8;64];
+	if key.len() > block.len() {
+		block[..32].copy_from_slice(&sha256(key));
+	} else {
+		block[..key.len()].copy_from_slice(key);
+	}
+	let mut inner = Sha256::new();
+	inner.update(block.map(|x| x ^ 0x36));
+	for part in parts {
+		inner.update(part);
+	}
+	let mut outer = Sha256::new();
+	outer.update(block.map(|x| x ^ 0x5c));
+	outer.update(inner.finalize());
+	outer.finalize().into()
+}
+
+// The MAC of a container: the header (as it would be serialized without the MAC) followed by the serialized offsets.
+pub(crate) fn container_mac(key: &str, header: &Header, body: &[u8]) -> Result<[u8;32],MetastegError> {
+	let mut unauthenticated = header.clone();
+	unauthenticated.mac = None;
+	Ok(hmac_sha256(key.as_bytes(), &[&header::serialize_header(&unauthenticated)?, body]))
+}
+
+// Check the MAC of a container against the key in the options. Without a key, there is nothing to check.
+fn check_mac(header: &Header, body: &[u8], options: &DecodeOptions) -> Result<(),MetastegError> {
+	match (&options.mac_key, header.mac) {
+		(None, None) => Ok(()),
+		(None, Some(_)) => Err(MetastegError::MacKeyRequired),
+		(Some(_), None) => Err(MetastegError::AuthenticationFailed),
+		(Some(key), Some(mac)) => {
+			// Compare every byte, so the time taken doesn't reveal how much of the MAC matched.
+			let difference = container_mac(key, header, body)?.iter().zip(mac).fold(0, |acc, (a, b)| acc | (a ^ b));
+			match difference {
+				0 => Ok(()),
+				_ => Err(MetastegError::AuthenticationFailed)
+			}
+		}
+	}
+}
+
+// Statistics about an encode, for showing or logging without having to work them out from the container.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodeReport {
+	// The length of the payload given, before any range was selected or compression applied.
+	pub payload_len: usize,
+	// The length of the image, or None if the encoder was made from an oracle rather than an image.
+	pub image_len: Option<usize>,
+	// The number of offsets encoded, including escape sentinels and literals.
+	pub offsets: usize,
+	pub container_len: usize,
+	// The number of byte values (out of 256) the oracle can encode.
+	pub coverage: usize,
+	// The number of different image offsets used.
+	pub distinct_offsets: usize
+}
+
+// Statistics about a decode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeReport {
+	pub container_len: usize,
+	pub image_len: usize,
+	// The number of offsets in the container, including escape sentinels and literals.
+	pub offsets: usize,
+	// The number of different image offsets used.
+	pub distinct_offsets: usize,
+	pub payload_len: usize
+}
+
+// Encode a payload with an image, returning the whole container (header and offsets).
+pub fn encode_bytes(payload: &[u8], image: &[u8], options: &EncodeOptions) -> Result<Vec<u8>,MetastegError> {
+	Encoder::new(image, options)?.encode_container(payload)
+}
+
+// Encode a payload read from a reader, such as a socket, a decompressing reader or stdin.
+// If the options allow encoding as a stream, the payload is encoded a chunk at a time as it is read, so it never has to be held in memory whole.
+// Otherwise it is read to the end and then encoded. The image is needed as a slice either way, since offsets can point anywhere in it.
+pub fn encode_reader<R: Read>(mut payload: R, image: &[u8], options: &EncodeOptions) -> Result<Vec<u8>,MetastegError> {
+	let mut encoder = Encoder::new(image, options)?;
+	if encoder.check_streamable().is_err() {
+		let mut buffered : Vec<u8> = Vec::new();
+		payload.read_to_end(&mut buffered)?;
+		return encoder.encode_container(&buffered);
+	}
+	let mut container : Vec<u8> = Vec::new();
+	let mut chunk = vec![0u8; READ_CHUNK_SIZE];
+	loop {
+		let length = match payload.read(&mut chunk) {
+			Ok(0) => break,
+			Ok(x) => x,
+			Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+			Err(e) => return Err(e.into())
+		};
+		container.extend(encoder.update(&chunk[..length])?);
+	}
+	container.extend(encoder.finish()?);
+	Ok(container)
+}
+
+// Encode a payload like encode_bytes, and also report on the result.
+pub fn encode_with_report(payload: &[u8], image: &[u8], options: &EncodeOptions) -> Result<(Vec<u8>, EncodeReport),MetastegError> {
+	Encoder::new(image, options)?.encode_container_with_report(payload)
+}
+
+// Encode a payload with every combination of offset width, format and compression, and return the smallest container.
+// Everything else is taken from the options, and the options that produced the winner are returned with it.
+// Combinations that can't encode the payload (offsets too wide for the width, say) are skipped.
+pub fn encode_optimized(payload: &[u8], image: &[u8], options: &EncodeOptions) -> Result<(EncodeOptions, Vec<u8>),MetastegError> {
+	let mut best : Option<(EncodeOptions, Vec<u8>)> = None;
+	let mut first_error : Option<MetastegError> = None;
+	for format in [Format::Fixed, Format::Varint, Format::Planar, Format::Dictionary] {
+		for width in WIDTHS {
+			for compress_offsets in [false, true] {
+				for compress_payload in [false, true] {
+					let candidate = EncodeOptions { format, width, compress_offsets, compress_payload, ..options.clone() };
+					match encode_bytes(payload, image, &candidate) {
+						Ok(container) if best.as_ref().is_none_or(|(_, x)| container.len() < x.len()) => best = Some((candidate, container)),
+						Ok(_) => (),
+						Err(e) => { first_error.get_or_insert(e); }
+					}
+				}
+			}
+		}
+	}
+	match (best, first_error) {
+		(Some(x), _) => Ok(x),
+		(None, Some(e)) => Err(e),
+		// Only possible if there were no combinations to try at all.
+		(None, None) => Err(MetastegError::UnsupportedFeature("there are no combinations of options to optimize over".to_string()))
+	}
+}
+
+// The options that encode a payload with the same settings as a container, as far as its header records them.
+// Keys aren't recorded, so the permutation and MAC keys come from the decode options. Seeds and payload ranges aren't recorded either, so they are lost.
+pub fn encode_options_for(header: &Header, options: &DecodeOptions) -> Result<EncodeOptions,MetastegError> {
+	if header.flags & header::FLAG_PERMUTED != 0 && options.permute_key.is_none() {
+		return Err(MetastegError::UnsupportedFeature("re-encoding a permuted container needs its permutation key".to_string()));
+	}
+	if header.mac.is_some() && options.mac_key.is_none() {
+		return Err(MetastegError::UnsupportedFeature("re-encoding an authenticated container needs its MAC key".to_string()));
+	}
+	Ok(EncodeOptions {
+		max_offset: header.max_offset,
+		width: header.width,
+		permute_key: options.permute_key.clone().filter(|_| header.flags & header::FLAG_PERMUTED != 0),
+		disguise: header.disguise,
+		checksum: header.checksum.is_some(),
+		fingerprint: header.fingerprint.is_some(),
+		escape: header.sentinel.is_some(),
+		compress_payload: header.flags & header::FLAG_COMPRESSED_PAYLOAD != 0,
+		compress_offsets: header.flags & header::FLAG_COMPRESSED_OFFSETS != 0,
+		format: Format::from_flags(header.flags),
+		image_list: header.images.is_some(),
+		mac_key: options.mac_key.clone().filter(|_| header.mac.is_some()),
+		content_type: header.content_type.clone(),
+		regions: header.regions.clone(),
+		archive: header.flags & header::FLAG_ARCHIVE != 0,
+		transform: header.transform,
+		sliding_window: header.sliding_window,
+		record_image_length: header.image_length.is_some(),
+		..EncodeOptions::default()
+	})
+}
+
+// Re-encode a container against a different image, so that it decodes to the same payload with the new image instead of the old one.
+// The payload is decoded with the old image (checking its checksum, if there is one) and encoded again with the same settings.
+pub fn transcode(container: &[u8], old_image: &[u8], new_image: &[u8], options: &DecodeOptions) -> Result<Vec<u8>,MetastegError> {
+	let parsed = Container::parse_with(container, options)?;
+	let payload = parsed.decode(old_image)?;
+	encode_bytes(&payload, new_image, &encode_options_for(&parsed.header, options)?)
+}
+
+// A parsed container: its header, and its offsets in payload order, in whichever format they were serialized.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Container {
+	pub header: Header,
+	pub offsets: Offsets
+}
+
+impl Container {
+	// Parse a container that isn't permuted or authenticated.
+	pub fn parse(bytes: &[u8]) -> Result<Container,MetastegError> {
+		Container::parse_with(bytes, &DecodeOptions::default())
+	}
+
+	// Parse a container, using the options for the permutation and MAC keys and the expected width.
+	pub fn parse_with(bytes: &[u8], options: &DecodeOptions) -> Result<Container,MetastegError> {
+		#[cfg(feature = "profile")]
+		let _span = tracing::info_span!("parse").entered();
+		let (header, offsets) = parse_offsets(bytes, options)?;
+		Ok(Container { header, offsets })
+	}
+
+	// Decode the payload with the image, checking the image fingerprint and list and the payload checksum if there are any.
+	pub fn decode(&self, image: &[u8]) -> Result<Vec<u8>,MetastegError> {
+		#[cfg(feature = "profile")]
+		let _span = tracing::info_span!("decode").entered();
+		check_fingerprint(&self.header, image)?;
+		check_images(&self.header, &[image])?;
+		let decoded = decode_unverified(&self.header, &self.offsets, image)?;
+		if let Some(checksum) = self.header.checksum {
+			if sha256(&decoded) != checksum {
+				return Err(MetastegError::ChecksumMismatch);
+			}
+		}
+		Ok(decoded)
+	}
+}
+
+// Decode a whole container with the image used to encode it.
+pub fn decode_bytes(container: &[u8], image: &[u8], options: &DecodeOptions) -> Result<Vec<u8>,MetastegError> {
+	Ok(decode_with_report(container, image, options)?.0)
+}
+
+// Decode a container like decode_bytes, and also report on it.
+pub fn decode_with_report(container: &[u8], image: &[u8], options: &DecodeOptions) -> Result<(Vec<u8>, DecodeReport),MetastegError> {
+	let container_len = container.len();
+	let container = Container::parse_with(container, options)?;
+	if options.strict_offsets {
+		check_strict_offsets(&container.header, &container.offsets, image.len())?;
+	}
+	let decoded = container.decode(image)?;
+	let report = DecodeReport {
+		container_len,
+		image_len: image.len(),
+		offsets: container.offsets.len(),
+		distinct_offsets: image_offsets(&container.header, &container.offsets).iter().collect::<BTreeSet<&u32>>().len(),
+		payload_len: decoded.len()
+	};
+	Ok((decoded, report))
+}
+
+// The length of the image the header records, either on its own or as the only entry of an image list.
+fn recorded_image_length(header: &Header) -> Result<u64,MetastegError> {
+	match (header.image_length, &header.images) {
+		(Some(length), _) => Ok(length),
+		(None, Some(images)) if images.len() == 1 => Ok(images[0].1),
+		_ => Err(MetastegError::UnsupportedFeature("strict offsets need a container that records its image length".to_string()))
+	}
+}
+
+// Check the offsets lie inside both the recorded image and the one given, then that the two have the same length.
+// Checking the offsets first means a bigger image that happens to contain every offset is reported by the first one it shouldn't have needed.
+pub(crate) fn check_strict_offsets(header: &Header, offsets: &Offsets, image_len: usize) -> Result<(),MetastegError> {
+	let recorded = recorded_image_length(header)?;
+	let shorter = recorded.min(image_len as u64);
+	let length = match header.transform {
+		Some(x) => x.output_length(shorter),
+		None => shorter
+	};
+	// Window-relative offsets are turned back into image offsets first, placing the window in the image given, just as decoding would.
+	let absolute;
+	let offsets = match header.sliding_window {
+		Some(window) => {
+			let transformed = match header.transform {
+				Some(x) => x.output_length(image_len as u64),
+				None => image_len as u64
+			};
+			absolute = window.to_absolute(offsets, 0, transformed as usize)?;
+			&absolute
+		},
+		None => offsets
+	};
+	if let Some(offset) = image_offsets(header, offsets).iter().find(|offset| **offset as u64 >= length) {
+		return Err(MetastegError::OffsetBeyondImage { offset: *offset, length });
+	}
+	check_image_length(header, image_len)
+}
+
+// Check the image given has the length recorded in the header.
+pub(crate) fn check_image_length(header: &Header, image_len: usize) -> Result<(),MetastegError> {
+	let recorded = recorded_image_length(header)?;
+	match recorded == image_len as u64 {
+		true => Ok(()),
+		false => Err(MetastegError::ImageLengthMismatch { recorded, given: image_len as u64 })
+	}
+}
+
+// Parse a container into its header and offsets, undoing any permutation so the offsets are in payload order.
+pub fn parse_offsets(container: &[u8], options: &DecodeOptions) -> Result<(Header, Offsets),MetastegError> {
+	let (header, body_start) = header::parse_header(container)?;
+	check_declared_width(&header, options)?;
+	let body = &container[body_start..];
+	check_mac(&header, body, options)?;
+	let inflated;
+	let serialized_payload = if header.flags & header::FLAG_COMPRESSED_OFFSETS != 0 {
+		inflated = compress::inflate(body)?;
+		&inflated
+	} else {
+		body
+	};
+	let mut offsets = Offsets::from(codec::codec_for(&header).deserialize(serialized_payload)?);
+	if header.flags & header::FLAG_PERMUTED != 0 {
+		let key = match &options.permute_key {
+			Some(x) => x,
+			None => return Err(MetastegError::PermutationKeyRequired)
+		};
+		offsets = Offsets::from(permute::unpermute(&offsets, key));
+	}
+	Ok((header, offsets))
+}
+
+// Translate offsets parsed from a container back into payload bytes (inflating them if need be), without checking the checksum or fingerprint.
+// The image is given as it is, and any transform recorded in the header is applied to it first.
+pub fn decode_unverified(header: &Header, offsets: &Offsets, image: &[u8]) -> Result<Vec<u8>,MetastegError> {
+	let image = transform::transform_image(header.transform, image);
+	let absolute;
+	let offsets = match header.sliding_window {
+		Some(window) => {
+			absolute = window.to_absolute(offsets, 0, image.len())?;
+			&absolute
+		},
+		None => offsets
+	};
+	let mut escaped = false;
+	let decoded = decode_offsets(header, offsets, &image, &mut escaped)?;
+	check_escape_complete(escaped)?;
+	if header.flags & header::FLAG_COMPRESSED_PAYLOAD != 0 {
+		return compress::inflate(&decoded);
+	}
+	Ok(decoded)
+}
+
+// The offsets that index into the image, leaving out escape sentinels and the literals that follow them.
+pub fn image_offsets(header: &Header, offsets: &Offsets) -> Offsets {
+	let mut result = Offsets::new();
+	let mut escaped = false;
+	for offset in offsets {
+		if escaped {
+			escaped = false;
+		} else if Some(*offset) == header.sentinel {
+			escaped = true;
+		} else {
+			result.push(*offset);
+		}
+	}
+	result
+}
+
+pub(crate) fn check_width(width: u8) -> Result<(),MetastegError> {
+	if !WIDTHS.contains(&width) {
+		return Err(MetastegError::UnsupportedWidth(width));
+	}
+	Ok(())
+}
+
+// Check the offset width declared by a header is supported, and is the expected one if there is one.
+pub(crate) fn check_declared_width(header: &Header, options: &DecodeOptions) -> Result<(),MetastegError> {
+	check_width(header.width)?;
+	if let Some(expected) = options.expect_width {
+		if header.width != expected {
+			return Err(MetastegError::WidthMismatch { declared: header.width, expected });
+		}
+	}
+	Ok(())
+}
+
+// Check that a serialized payload holds a whole number of offsets of the given width.
+pub(crate) fn check_length(length: usize, width: u8) -> Result<(),MetastegError> {
+	let remainder = length % width as usize;
+	if remainder != 0 {
+		return Err(MetastegError::InvalidLength { length, width, remainder });
+	}
+	Ok(())
+}
+
+// Check a list of images against the image list recorded in the header, if there is one.
+pub fn check_images(header: &Header, images: &[&[u8]]) -> Result<(),MetastegError> {
+	let expected = match &header.images {
+		Some(x) => x,
+		None => return Ok(())
+	};
+	if expected.len() != images.len() {
+		return Err(MetastegError::ImageCountMismatch { expected: expected.len(), given: images.len() });
+	}
+	for (i, ((hash, length), image)) in expected.iter().zip(images).enumerate() {
+		if image.len() as u64 != *length || sha256(image) != *hash {
+			return Err(MetastegError::ImageListMismatch { index: i + 1, count: images.len() });
+		}
+	}
+	Ok(())
+}
+
+// Check the image against the fingerprint recorded in the header, if there is one.
+pub(crate) fn check_fingerprint(header: &Header, image: &[u8]) -> Result<(),MetastegError> {
+	match header.fingerprint {
+		Some(fingerprint) if sha256(image) != fingerprint => Err(MetastegError::ImageMismatch),
+		_ => Ok(())
+	}
+}
+
+// Check offsets against the header and translate them back into payload bytes.
+// For escaped containers, escaped tracks whether the last offset seen was the sentinel, so that a literal
+// can follow in the next call when decoding a stream.
+pub(crate) fn decode_offsets(header: &Header, offsets: &Offsets, image: &[u8], escaped: &mut bool) -> Result<Vec<u8>,MetastegError> {
+	// Check the offsets are consistent with the window recorded at encode time.
+	let check_window = |offset: u32| match header.max_offset {
+		Some(max_offset) if offset >= max_offset => Err(MetastegError::OffsetOutsideWindow { offset, max_offset }),
+		_ => Ok(())
+	};
+	// And that each one decodes to a value that was allowed to come from that part of the image.
+	let check_region = |offset: u32, byte: u8| match region::region_allows(&header.regions, byte, offset) {
+		true => Ok(()),
+		false => Err(MetastegError::OffsetOutsideRegion { offset, byte })
+	};
+	let sentinel = match header.sentinel {
+		Some(x) => x,
+		None => {
+			for offset in offsets {
+				check_window(*offset)?;
+			}
+			let decoded = match oracle::metasteg_decode(offsets, image) {
+				Ok(x) => x,
+				Err(e) => return Err(MetastegError::OffsetOutOfBounds(e as u64))
+			};
+			if !header.regions.is_empty() {
+				for (offset, byte) in offsets.iter().zip(&decoded) {
+					check_region(*offset, *byte)?;
+				}
+			}
+			return Ok(decoded);
+		}
+	};
+	// Each sentinel is followed by the literal value of a byte that was missing from the image.
+	let mut decoded : Vec<u8> = Vec::new();
+	for offset in offsets {
+		if *escaped {
+			*escaped = false;
+			match u8::try_from(*offset) {
+				Ok(x) => decoded.push(x),
+				Err(_) => return Err(MetastegError::InvalidEscape(format!("escaped literal {} is not a byte value", offset)))
+			};
+		} else if *offset == sentinel {
+			*escaped = true;
+		} else {
+			check_window(*offset)?;
+			match image.get(*offset as usize) {
+				Some(x) => {
+					check_region(*offset, *x)?;
+					decoded.push(*x);
+				},
+				None => return Err(MetastegError::OffsetOutOfBounds(*offset as u64))
+			};
+		}
+	}
+	Ok(decoded)
+}
+
+// Check that an escaped container didn't end straight after a sentinel.
+pub(crate) fn check_escape_complete(escaped: bool) -> Result<(),MetastegError> {
+	if escaped {
+		return Err(MetastegError::InvalidEscape("serialized payload ends with a sentinel but no literal".to_string()));
+	}
+	Ok(())
+}
+
+// The sentinel for escaped literals: the largest offset the width can hold, which real offsets must never reach.
+pub(crate) fn sentinel_for_width(width: u8) -> u32 {
+	match width {
+		1 => u8::MAX as u32,
+		2 => u16::MAX as u32,
+		_ => u32::MAX
+	}
+}
@@ -0,0 +1,58 @@
+// Fake file-type preambles that can be written in front of a container so it looks like a common file.
+// This is purely cosmetic: the result starts like a PNG, PDF or ZIP file but won't open as one.
+
+// A minimal PNG signature followed by the IHDR chunk of a 1x1 greyscale image.
+const PNG_PREAMBLE : &[u8] = &[
+	0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44, 0x52,
+	0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x08, 0x00, 0x00, 0x00, 0x00, 0x3a, 0x7e, 0x9b,
+	0x55
+];
+// A PDF version line followed by the customary binary comment.
+const PDF_PREAMBLE : &[u8] = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n";
+// The start of a ZIP local file header.
+const ZIP_PREAMBLE : &[u8] = b"PK\x03\x04\x14\x00\x00\x00\x08\x00";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Disguise {
+	Png,
+	Pdf,
+	Zip
+}
+
+pub const DISGUISES : [Disguise;3] = [Disguise::Png, Disguise::Pdf, Disguise::Zip];
+
+impl Disguise {
+	pub fn from_name(name: &str) -> Option<Disguise> {
+		DISGUISES.iter().find(|disguise| disguise.name() == name).copied()
+	}
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			Disguise::Png => "png",
+			Disguise::Pdf => "pdf",
+			Disguise::Zip => "zip"
+		}
+	}
+
+	// The bytes written in front of the real container.
+	pub fn preamble(&self) -> &'static [u8] {
+		match self {
+			Disguise::Png => PNG_PREAMBLE,
+			Disguise::Pdf => PDF_PREAMBLE,
+			Disguise::Zip => ZIP_PREAMBLE
+		}
+	}
+
+	// The identifier recorded in the container header.
+	pub fn id(&self) -> u8 {
+		match self {
+			Disguise::Png => 1,
+			Disguise::Pdf => 2,
+			Disguise::Zip => 3
+		}
+	}
+
+	pub fn from_id(id: u8) -> Option<Disguise> {
+		DISGUISES.iter().find(|disguise| disguise.id() == id).copied()
+	}
+}
@@ -0,0 +1,162 @@
+// The error type returned by everything in the library.
+use std::fmt;
+use std::io;
+
+use crate::oracle::RareByte;
+
+#[derive(Debug)]
+pub enum MetastegError {
+	// Reading or writing a file failed. The context names the file and what it was being used as.
+	Io { context: Option<String>, source: io::Error },
+	// The image has no occurrence of a byte value (below the offset window, if there is one).
+	MissingByteInImage { byte: u8, max_offset: Option<u32> },
+	// The image has every byte value, but the transform applied to it loses some.
+	MissingByteAfterTransform { byte: u8, transform: &'static str },
+	// The range of the payload selected for encoding doesn't lie inside the payload.
+	InvalidPayloadRange { offset: u64, length: u64, payload_len: usize },
+	// The payload contains a byte value the oracle can't translate.
+	UnencodableByte(u8),
+	// Every offset of a payload byte in the image serializes to a forbidden byte.
+	NoAllowedOffset(u8),
+	// A payload byte has no occurrence inside the image regions it is restricted to.
+	NoOffsetInRegion(u8),
+	// An offset doesn't index into the image.
+	OffsetOutOfBounds(u64),
+	// An offset is too large to be serialized with the offset width in use.
+	OffsetTooWide { offset: u32, width: u8 },
+	// An offset lies outside the window recorded in the container header.
+	OffsetOutsideWindow { offset: u32, max_offset: u32 },
+	// An offset lies beyond the end of the image recorded in the header, or the image given, whichever is shorter.
+	OffsetBeyondImage { offset: u32, length: u64 },
+	// The image given has a different length from the one recorded in the header.
+	ImageLengthMismatch { recorded: u64, given: u64 },
+	// A payload byte doesn't occur inside the sliding window at its position.
+	NoOffsetInSlidingWindow { byte: u8, position: usize },
+	// Some payload byte values occur fewer times in the image than the minimum asked for.
+	TooFewOccurrences { minimum: u64, rare: Vec<RareByte> },
+	// The sliding window doesn't fit inside the image.
+	WindowLargerThanImage { size: u32, image_len: u64 },
+	// A payload byte doesn't occur after the offset chosen for the byte before it, so the offsets can't keep increasing.
+	NoMonotonicOffset { byte: u8, position: usize, after: u32 },
+	// An offset decodes to a byte value that isn't allowed to come from that part of the image.
+	OffsetOutsideRegion { offset: u32, byte: u8 },
+	UnsupportedWidth(u8),
+	// The header declares a different offset width from the one the caller expected.
+	WidthMismatch { declared: u8, expected: u8 },
+	// The serialized offsets don't divide evenly into offsets of the declared width.
+	InvalidLength { length: usize, width: u8, remainder: usize },
+	// The serialized offsets are malformed for the format declared in the header.
+	InvalidOffsets(String),
+	InvalidHeader(String),
+	// A header field's value is too long for its two-byte length.
+	HeaderFieldTooLong { field: &'static str, length: usize },
+	// A real offset would collide with the sentinel reserved for escaped literals.
+	SentinelCollision(u32),
+	// An escaped literal is malformed.
+	InvalidEscape(String),
+	// The decoded payload doesn't match the checksum recorded in the header, usually because the image is wrong.
+	ChecksumMismatch,
+	// The image doesn't match the fingerprint recorded in the header.
+	ImageMismatch,
+	// One of the images doesn't match the image list recorded in the header. The index counts from 1.
+	ImageListMismatch { index: usize, count: usize },
+	// A different number of images was given from the number recorded in the header.
+	ImageCountMismatch { expected: usize, given: usize },
+	// A multi-payload archive is malformed, has an unsafe entry name, or an entry doesn't match its checksum.
+	InvalidArchive(String),
+	// A bundle's trailer is missing or doesn't fit the file.
+	InvalidBundle(String),
+	// A tar or zip package of a container is malformed or has no container in it.
+	InvalidPackage(String),
+	// Compressed data in the container couldn't be inflated.
+	InvalidCompression(String),
+	// The container's offsets are permuted, but no key was given to undo the permutation.
+	PermutationKeyRequired,
+	// The container carries a MAC, but no key was given to check it.
+	MacKeyRequired,
+	// The MAC in the header doesn't match the container, or it is missing when a key was given.
+	AuthenticationFailed,
+	// An audit manifest is malformed.
+	InvalidManifest(String),
+	// The files or the signature don't match an audit manifest.
+	AuditMismatch(String),
+	UnsupportedFeature(String)
+}
+
+impl MetastegError {
+	// An error reading a file, naming what the file was for (payload, image, container...) and its path.
+	pub fn io_read(kind: &str, path: &str, source: io::Error) -> MetastegError {
+		MetastegError::Io { context: Some(format!("failed to read {} '{}'", kind, path)), source }
+	}
+
+	// An error writing a file, naming what the file was for and its path.
+	pub fn io_write(kind: &str, path: &str, source: io::Error) -> MetastegError {
+		MetastegError::Io { context: Some(format!("failed to write {} '{}'", kind, path)), source }
+	}
+}
+
+impl fmt::Display for MetastegError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			MetastegError::Io { context: Some(context), source } => write!(f, "{}: {}", context, source),
+			MetastegError::Io { context: None, source } => write!(f, "{}", source),
+			MetastegError::MissingByteInImage { byte, max_offset: Some(max_offset) } => write!(f, "Failed to create oracle; could not produce an offset below {} for value 0x{:02x}", max_offset, byte),
+			MetastegError::MissingByteInImage { byte, max_offset: None } => write!(f, "Failed to create oracle; could not produce an offset for value 0x{:02x}", byte),
+			MetastegError::MissingByteAfterTransform { byte, transform } => write!(f, "Failed to create oracle; the image has no value 0x{:02x} after the {} transform", byte, transform),
+			MetastegError::InvalidPayloadRange { offset, length, payload_len } => write!(f, "Payload range of {} bytes at offset {} lies outside the {}-byte payload", length, offset, payload_len),
+			MetastegError::UnencodableByte(byte) => write!(f, "Failed to encode payload with oracle; failed on byte {}", byte),
+			MetastegError::NoAllowedOffset(byte) => write!(f, "Failed to encode payload; every offset for value 0x{:02x} contains a forbidden byte when serialized", byte),
+			MetastegError::NoOffsetInRegion(byte) => write!(f, "Failed to encode payload; value 0x{:02x} does not occur in the image regions allowed for it", byte),
+			MetastegError::NoOffsetInSlidingWindow { byte, position } => write!(f, "Failed to encode payload; value 0x{:02x} at payload position {} does not occur in the sliding window there; try a larger window", byte, position),
+			MetastegError::NoMonotonicOffset { byte, position, after } => write!(f, "Failed to encode payload monotonically; value 0x{:02x} at payload position {} does not occur after offset {} in the image", byte, position, after),
+			MetastegError::TooFewOccurrences { minimum, rare } => {
+				let listed : Vec<String> = rare.iter().map(|x| format!("0x{:02x} ({})", x.byte, x.occurrences)).collect();
+				write!(f, "Failed to encode payload; {} byte values in the payload occur fewer than {} times in the image: {}", rare.len(), minimum, listed.join(", "))
+			},
+			MetastegError::WindowLargerThanImage { size, image_len } => write!(f, "The {}-byte sliding window does not fit in the {}-byte image", size, image_len),
+			MetastegError::OffsetBeyondImage { offset, length } => write!(f, "Offset {} lies beyond the {} bytes the image is recorded to have", offset, length),
+			MetastegError::ImageLengthMismatch { recorded, given } => write!(f, "The container was encoded with a {}-byte image, but the image given has {} bytes", recorded, given),
+			MetastegError::OffsetOutOfBounds(offset) => write!(f, "Failed to decode payload with image; failure on offset {}", offset),
+			MetastegError::OffsetTooWide { offset, width } => write!(f, "Offset {} does not fit in {} bytes; use a wider offset width", offset, width),
+			MetastegError::OffsetOutsideWindow { offset, max_offset } => write!(f, "Offset {} lies outside the window recorded in the header (below {})", offset, max_offset),
+			MetastegError::OffsetOutsideRegion { offset, byte } => write!(f, "Offset {} decodes to 0x{:02x}, which lies outside the image regions recorded for that value in the header", offset, byte),
+			MetastegError::UnsupportedWidth(width) => write!(f, "Unsupported offset width: {}", width),
+			MetastegError::WidthMismatch { declared, expected } => write!(f, "Container header declares an offset width of {} but {} was expected", declared, expected),
+			MetastegError::InvalidLength { length, width, remainder } => write!(f, "Serialized payload has an invalid length: {} is not a multiple of the offset width {} ({} bytes left over)", length, width, remainder),
+			MetastegError::InvalidOffsets(reason) => write!(f, "Invalid serialized offsets: {}", reason),
+			MetastegError::InvalidHeader(reason) => write!(f, "Invalid container header: {}", reason),
+			MetastegError::HeaderFieldTooLong { field, length } => write!(f, "The {} is {} bytes long, but a header field can hold at most 65535", field, length),
+			MetastegError::SentinelCollision(sentinel) => write!(f, "Offset {} is used by the image but is reserved as the escape sentinel; use a wider offset width", sentinel),
+			MetastegError::InvalidEscape(reason) => write!(f, "Invalid escaped literal: {}", reason),
+			MetastegError::ChecksumMismatch => write!(f, "Decoded payload does not match the checksum in the container header; is this the right image?"),
+			MetastegError::ImageMismatch => write!(f, "Image does not match the fingerprint in the container header"),
+			MetastegError::ImageListMismatch { index, count } => write!(f, "Image {} of {} does not match the image list in the container header; are the images in the right order?", index, count),
+			MetastegError::ImageCountMismatch { expected, given } => write!(f, "Container was encoded with {} images but {} were given", expected, given),
+			MetastegError::InvalidArchive(reason) => write!(f, "Invalid payload archive: {}", reason),
+			MetastegError::InvalidBundle(reason) => write!(f, "Invalid bundle: {}", reason),
+			MetastegError::InvalidPackage(reason) => write!(f, "Invalid container package: {}", reason),
+			MetastegError::InvalidCompression(reason) => write!(f, "Failed to decompress container data: {}", reason),
+			MetastegError::PermutationKeyRequired => write!(f, "Container offsets are permuted; the permutation key is required to decode it"),
+			MetastegError::MacKeyRequired => write!(f, "Container is authenticated; the MAC key is required to decode it"),
+			MetastegError::AuthenticationFailed => write!(f, "Container failed authentication; it has been tampered with or the MAC key is wrong"),
+			MetastegError::InvalidManifest(reason) => write!(f, "Invalid audit manifest: {}", reason),
+			MetastegError::AuditMismatch(reason) => write!(f, "Audit failed: {}", reason),
+			MetastegError::UnsupportedFeature(feature) => write!(f, "Unsupported feature: {}", feature)
+		}
+	}
+}
+
+impl std::error::Error for MetastegError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			MetastegError::Io { source, .. } => Some(source),
+			_ => None
+		}
+	}
+}
+
+impl From<io::Error> for MetastegError {
+	fn from(source: io::Error) -> MetastegError {
+		MetastegError::Io { context: None, source }
+	}
+}
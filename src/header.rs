@@ -0,0 +1,333 @@
+// The header stored at the start of a container, describing how the offsets that follow it were produced.
+use crate::MetastegError;
+use crate::disguise::{Disguise, DISGUISES};
+use crate::region::Region;
+use crate::sliding::SlidingWindow;
+use crate::transform::Transform;
+
+// Magic bytes at the start of every container that carries a header.
+// Containers without them were produced before the header existed and are treated as a bare stream of 4-byte offsets.
+pub const MAGIC : &[u8;4] = b"MSTG";
+pub const VERSION : u8 = 1;
+
+// Header field tags. Each field is stored as a tag byte, a big-endian u16 length and then the value itself.
+// The list of fields is terminated by FIELD_END.
+const FIELD_END : u8 = 0;
+const FIELD_MAX_OFFSET : u8 = 1;
+const FIELD_DISGUISE : u8 = 2;
+const FIELD_CHECKSUM : u8 = 3;
+const FIELD_FINGERPRINT : u8 = 4;
+const FIELD_SENTINEL : u8 = 5;
+const FIELD_PAYLOAD_RANGE : u8 = 6;
+const FIELD_IMAGES : u8 = 7;
+const FIELD_MAC : u8 = 8;
+const FIELD_CONTENT_TYPE : u8 = 9;
+const FIELD_REGIONS : u8 = 10;
+const FIELD_TRANSFORM : u8 = 11;
+const FIELD_IMAGE_LENGTH : u8 = 12;
+const FIELD_SLIDING_WINDOW : u8 = 13;
+
+// Header flags.
+// The low 16 bits are critical: they change how the offsets or the payload have to be read, so a container with a critical flag
+// this version doesn't know is rejected rather than misread. The high 16 bits are for flags that are safe to ignore, and unknown ones are.
+// Every flag defined so far is critical.
+pub const CRITICAL_FLAGS : u32 = 0xffff;
+// The offsets are stored in an order scrambled by a keyed permutation.
+pub const FLAG_PERMUTED : u32 = 1;
+// The serialized offsets (everything after the header) are deflated.
+pub const FLAG_COMPRESSED_OFFSETS : u32 = 2;
+// The payload was deflated before being encoded, so it needs inflating after decoding.
+pub const FLAG_COMPRESSED_PAYLOAD : u32 = 4;
+// The offsets are serialized as varints rather than fixed-width integers.
+pub const FLAG_VARINT_OFFSETS : u32 = 8;
+// The fixed-width offsets are stored one byte plane at a time, rather than one offset at a time.
+pub const FLAG_PLANAR_OFFSETS : u32 = 16;
+// The payload is an archive of named entries (see the archive module) rather than a single file.
+pub const FLAG_ARCHIVE : u32 = 32;
+// The offsets are stored as indices into a dictionary of the distinct offsets.
+pub const FLAG_DICTIONARY_OFFSETS : u32 = 64;
+// Every flag this version understands.
+pub const KNOWN_FLAGS : u32 = FLAG_PERMUTED | FLAG_COMPRESSED_OFFSETS | FLAG_COMPRESSED_PAYLOAD | FLAG_VARINT_OFFSETS | FLAG_PLANAR_OFFSETS | FLAG_ARCHIVE | FLAG_DICTIONARY_OFFSETS;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+	pub width: u8,
+	pub flags: u32,
+	pub max_offset: Option<u32>,
+	// The fake file-type preamble written in front of the header, if any.
+	pub disguise: Option<Disguise>,
+	// The SHA-256 of the payload, checked after decoding.
+	pub checksum: Option<[u8;32]>,
+	// The SHA-256 of the image, checked before decoding.
+	pub fingerprint: Option<[u8;32]>,
+	// The reserved offset that marks an escaped literal byte, for containers encoded with escapes.
+	pub sentinel: Option<u32>,
+	// The offset and length of the part of the original payload that was encoded, for information only.
+	pub payload_range: Option<(u64, u64)>,
+	// The SHA-256 and length of each image the container was encoded with, in the order decode needs them.
+	pub images: Option<Vec<([u8;32], u64)>>,
+	// An HMAC-SHA256 of the rest of the header and the serialized offsets, checked before decoding.
+	pub mac: Option<[u8;32]>,
+	// The MIME type of the payload, as a hint for whoever decodes it. For information only.
+	pub content_type: Option<String>,
+	// The image regions each byte value was restricted to, checked while decoding.
+	pub regions: Vec<Region>,
+	// The transform applied to the image before the offsets were chosen, which decode has to apply too.
+	pub transform: Option<Transform>,
+	// The length of the image the container was encoded with, for strict decoding.
+	pub image_length: Option<u64>,
+	// The sliding window the offsets are relative to, which decode has to move through the image the same way.
+	pub sliding_window: Option<SlidingWindow>
+}
+
+impl Header {
+	pub fn new() -> Header {
+		Header { width: 4, flags: 0, max_offset: None, disguise: None, checksum: None, fingerprint: None, sentinel: None, payload_range: None, images: None, mac: None, content_type: None, regions: Vec::new(), transform: None, image_length: None, sliding_window: None }
+	}
+}
+
+impl Default for Header {
+	fn default() -> Header {
+		Header::new()
+	}
+}
+
+// Serialize a header, ready to be written in front of the encoded offsets.
+// If the header has a disguise, its preamble is written first.
+pub fn serialize_header(header: &Header) -> Result<Vec<u8>,MetastegError> {
+	let mut serialized : Vec<u8> = Vec::new();
+	if let Some(disguise) = header.disguise {
+		serialized.extend_from_slice(disguise.preamble());
+	}
+	serialized.extend_from_slice(MAGIC);
+	serialized.push(VERSION);
+	serialized.push(header.width);
+	serialized.extend_from_slice(&header.flags.to_be_bytes());
+	if let Some(max_offset) = header.max_offset {
+		push_field(&mut serialized, FIELD_MAX_OFFSET, &max_offset.to_be_bytes())?;
+	}
+	if let Some(disguise) = header.disguise {
+		push_field(&mut serialized, FIELD_DISGUISE, &[disguise.id()])?;
+	}
+	if let Some(checksum) = header.checksum {
+		push_field(&mut serialized, FIELD_CHECKSUM, &checksum)?;
+	}
+	if let Some(fingerprint) = header.fingerprint {
+		push_field(&mut serialized, FIELD_FINGERPRINT, &fingerprint)?;
+	}
+	if let Some(sentinel) = header.sentinel {
+		push_field(&mut serialized, FIELD_SENTINEL, &sentinel.to_be_bytes())?;
+	}
+	if let Some((offset, length)) = header.payload_range {
+		let mut value = offset.to_be_bytes().to_vec();
+		value.extend_from_slice(&length.to_be_bytes());
+		push_field(&mut serialized, FIELD_PAYLOAD_RANGE, &value)?;
+	}
+	if let Some(images) = &header.images {
+		let mut value : Vec<u8> = Vec::new();
+		for (hash, length) in images {
+			value.extend_from_slice(hash);
+			value.extend_from_slice(&length.to_be_bytes());
+		}
+		push_field(&mut serialized, FIELD_IMAGES, &value)?;
+	}
+	if !header.regions.is_empty() {
+		let mut value : Vec<u8> = Vec::new();
+		for region in &header.regions {
+			value.extend_from_slice(&[*region.values.start(), *region.values.end()]);
+			value.extend_from_slice(&region.offsets.start.to_be_bytes());
+			value.extend_from_slice(&region.offsets.end.to_be_bytes());
+		}
+		push_field(&mut serialized, FIELD_REGIONS, &value)?;
+	}
+	if let Some(transform) = header.transform {
+		push_field(&mut serialized, FIELD_TRANSFORM, &[transform.id()])?;
+	}
+	if let Some(image_length) = header.image_length {
+		push_field(&mut serialized, FIELD_IMAGE_LENGTH, &image_length.to_be_bytes())?;
+	}
+	if let Some(window) = header.sliding_window {
+		let mut value = window.size.to_be_bytes().to_vec();
+		value.extend_from_slice(&window.advance.to_be_bytes());
+		push_field(&mut serialized, FIELD_SLIDING_WINDOW, &value)?;
+	}
+	if let Some(content_type) = &header.content_type {
+		push_field(&mut serialized, FIELD_CONTENT_TYPE, content_type.as_bytes())?;
+	}
+	if let Some(mac) = header.mac {
+		push_field(&mut serialized, FIELD_MAC, &mac)?;
+	}
+	serialized.push(FIELD_END);
+	Ok(serialized)
+}
+
+// Append a field as its tag, a two-byte length and the value. A value too long for the length is an error rather than being cut short.
+fn push_field(serialized: &mut Vec<u8>, tag: u8, value: &[u8]) -> Result<(),MetastegError> {
+	let length = match u16::try_from(value.len()) {
+		Ok(x) => x,
+		Err(_) => return Err(MetastegError::HeaderFieldTooLong { field: field_name(tag), length: value.len() })
+	};
+	serialized.push(tag);
+	serialized.extend_from_slice(&length.to_be_bytes());
+	serialized.extend_from_slice(value);
+	Ok(())
+}
+
+// What a field holds, for errors about it.
+fn field_name(tag: u8) -> &'static str {
+	match tag {
+		FIELD_MAX_OFFSET => "maximum offset",
+		FIELD_DISGUISE => "disguise",
+		FIELD_CHECKSUM => "checksum",
+		FIELD_FINGERPRINT => "fingerprint",
+		FIELD_SENTINEL => "sentinel",
+		FIELD_PAYLOAD_RANGE => "payload range",
+		FIELD_IMAGES => "image list",
+		FIELD_MAC => "MAC",
+		FIELD_CONTENT_TYPE => "content type",
+		FIELD_REGIONS => "region list",
+		FIELD_TRANSFORM => "transform",
+		FIELD_IMAGE_LENGTH => "image length",
+		FIELD_SLIDING_WINDOW => "sliding window",
+		_ => "unknown"
+	}
+}
+
+// Parse the header at the start of a container.
+// Returns the header along with the position at which the encoded offsets begin.
+pub fn parse_header(buf: &[u8]) -> Result<(Header, usize),MetastegError> {
+	match parse_header_partial(buf)? {
+		Some(x) => Ok(x),
+		None if buf.is_empty() => Ok((Header::new(), 0)),
+		None => Err(MetastegError::InvalidHeader("header is truncated".to_string()))
+	}
+}
+
+// Parse the header at the start of a buffer that may not hold the whole container yet.
+// Returns None if more bytes are needed to tell whether there is a header, or to read all of it.
+pub fn parse_header_partial(buf: &[u8]) -> Result<Option<(Header, usize)>,MetastegError> {
+	// Skip past a disguise preamble if there is one.
+	if is_partial(buf, MAGIC) || DISGUISES.iter().any(|disguise| is_partial(buf, disguise.preamble())) {
+		return Ok(None);
+	}
+	let disguise = DISGUISES.iter().find(|disguise| buf.starts_with(disguise.preamble())).copied();
+	let start = match disguise {
+		Some(x) => x.preamble().len(),
+		None => 0
+	};
+	let buf = &buf[start..];
+	if is_partial(buf, MAGIC) {
+		return Ok(None);
+	}
+	if !buf.starts_with(MAGIC) {
+		match disguise {
+			Some(x) => return Err(MetastegError::InvalidHeader(format!("container is disguised as {} but no header follows the disguise", x.name()))),
+			None => return Ok(Some((Header::new(), 0)))
+		}
+	}
+	if buf.len() < 10 {
+		return Ok(None);
+	}
+	if buf[4] != VERSION {
+		return Err(MetastegError::InvalidHeader(format!("unsupported container version {}", buf[4])));
+	}
+	let mut header = Header::new();
+	header.width = buf[5];
+	header.flags = u32::from_be_bytes(buf[6..10].try_into().unwrap());
+	let unknown = header.flags & CRITICAL_FLAGS & !KNOWN_FLAGS;
+	if unknown != 0 {
+		return Err(MetastegError::UnsupportedFeature(format!("container uses critical flags {:#x} that this version doesn't understand", unknown)));
+	}
+
+	let mut i = 10;
+	loop {
+		if i >= buf.len() { return Ok(None); }
+		let tag = buf[i];
+		i += 1;
+		if tag == FIELD_END { break; }
+		if i + 2 > buf.len() { return Ok(None); }
+		let length = u16::from_be_bytes(buf[i..i+2].try_into().unwrap()) as usize;
+		i += 2;
+		if i + length > buf.len() { return Ok(None); }
+		let value = &buf[i..i+length];
+		i += length;
+		match tag {
+			FIELD_MAX_OFFSET => header.max_offset = Some(parse_u32_field(tag, value)?),
+			FIELD_DISGUISE => header.disguise = match value {
+				[id] => match Disguise::from_id(*id) {
+					Some(x) => Some(x),
+					None => return Err(MetastegError::InvalidHeader(format!("unknown disguise {}", id)))
+				},
+				_ => return Err(invalid_field_length(tag, value))
+			},
+			FIELD_SENTINEL => header.sentinel = Some(parse_u32_field(tag, value)?),
+			FIELD_PAYLOAD_RANGE => header.payload_range = match value.len() {
+				16 => Some((u64::from_be_bytes(value[..8].try_into().unwrap()), u64::from_be_bytes(value[8..].try_into().unwrap()))),
+				_ => return Err(invalid_field_length(tag, value))
+			},
+			FIELD_IMAGES => header.images = match value.len() % 40 {
+				0 => Some(value.chunks_exact(40).map(|x| (x[..32].try_into().unwrap(), u64::from_be_bytes(x[32..].try_into().unwrap()))).collect()),
+				_ => return Err(invalid_field_length(tag, value))
+			},
+			FIELD_CHECKSUM => header.checksum = Some(parse_hash_field(tag, value)?),
+			FIELD_FINGERPRINT => header.fingerprint = Some(parse_hash_field(tag, value)?),
+			FIELD_REGIONS => header.regions = match value.len() % 10 {
+				0 => value.chunks_exact(10).map(|x| Region {
+					values: x[0]..=x[1],
+					offsets: u32::from_be_bytes(x[2..6].try_into().unwrap())..u32::from_be_bytes(x[6..].try_into().unwrap())
+				}).collect(),
+				_ => return Err(invalid_field_length(tag, value))
+			},
+			FIELD_TRANSFORM => header.transform = match value {
+				[id] => match Transform::from_id(*id) {
+					Some(x) => Some(x),
+					None => return Err(MetastegError::InvalidHeader(format!("unknown image transform {}", id)))
+				},
+				_ => return Err(invalid_field_length(tag, value))
+			},
+			FIELD_IMAGE_LENGTH => header.image_length = match value.try_into() {
+				Ok(x) => Some(u64::from_be_bytes(x)),
+				Err(_) => return Err(invalid_field_length(tag, value))
+			},
+			FIELD_SLIDING_WINDOW => header.sliding_window = match value.len() {
+				8 => Some(SlidingWindow { size: u32::from_be_bytes(value[..4].try_into().unwrap()), advance: u32::from_be_bytes(value[4..].try_into().unwrap()) }),
+				_ => return Err(invalid_field_length(tag, value))
+			},
+			FIELD_CONTENT_TYPE => header.content_type = match String::from_utf8(value.to_vec()) {
+				Ok(x) => Some(x),
+				Err(_) => return Err(MetastegError::InvalidHeader("content type is not valid UTF-8".to_string()))
+			},
+			FIELD_MAC => header.mac = Some(parse_hash_field(tag, value)?),
+			_ => return Err(MetastegError::InvalidHeader(format!("unknown field {}", tag)))
+		}
+	}
+
+	if header.disguise != disguise {
+		return Err(MetastegError::InvalidHeader("disguise doesn't match the one recorded in the header".to_string()));
+	}
+
+	Ok(Some((header, start + i)))
+}
+
+// Whether a buffer holds the start of a marker, but not enough of it to tell whether the marker is really there.
+fn is_partial(buf: &[u8], marker: &[u8]) -> bool {
+	buf.len() < marker.len() && marker.starts_with(buf)
+}
+
+fn parse_u32_field(tag: u8, value: &[u8]) -> Result<u32,MetastegError> {
+	match value.try_into() {
+		Ok(x) => Ok(u32::from_be_bytes(x)),
+		Err(_) => Err(invalid_field_length(tag, value))
+	}
+}
+
+fn parse_hash_field(tag: u8, value: &[u8]) -> Result<[u8;32],MetastegError> {
+	match value.try_into() {
+		Ok(x) => Ok(x),
+		Err(_) => Err(invalid_field_length(tag, value))
+	}
+}
+
+fn invalid_field_length(tag: u8, value: &[u8]) -> MetastegError {
+	MetastegError::InvalidHeader(format!("field {} has an invalid length of {} bytes", tag, value.len()))
+}
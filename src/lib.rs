@@ -0,0 +1,54 @@
+// The core of metastego: building an oracle from an image, and encoding or decoding payloads with it.
+// The binary is a thin wrapper around this that deals with files and the command line.
+// Without the default std feature, only the oracle is available, for use in no_std environments with an allocator.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod archive;
+#[cfg(feature = "std")]
+pub mod audit;
+#[cfg(feature = "std")]
+pub mod bundle;
+pub mod chunk;
+#[cfg(feature = "std")]
+pub mod codec;
+#[cfg(feature = "std")]
+pub mod compress;
+#[cfg(feature = "std")]
+mod container;
+pub mod disguise;
+#[cfg(feature = "std")]
+pub mod error;
+#[cfg(feature = "std")]
+pub mod header;
+pub mod oracle;
+#[cfg(feature = "std")]
+pub mod package;
+#[cfg(feature = "std")]
+pub mod permute;
+#[cfg(feature = "std")]
+pub mod region;
+#[cfg(feature = "std")]
+pub mod rng;
+#[cfg(feature = "std")]
+pub mod sliding;
+#[cfg(feature = "std")]
+pub mod stream;
+#[cfg(feature = "std")]
+pub mod transform;
+
+#[cfg(feature = "std")]
+pub use codec::{OffsetCodec, Format};
+#[cfg(feature = "std")]
+pub use container::*;
+#[cfg(feature = "std")]
+pub use error::MetastegError;
+#[cfg(feature = "std")]
+pub use header::Header;
+#[cfg(feature = "std")]
+pub use stream::{Encoder, Decoder, build_oracle, build_partial_oracle};
+
+// Offset widths (in bytes) that a container can use.
+pub const WIDTHS : [u8;4] = [1, 2, 4, 8];
@@ -1,163 +1,1394 @@
 use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::env;
-use std::io::Write;
-use std::collections::HashMap;
-
-// Create an metasteganographic oracle from an array of bytes.
-// If it fails to find a corresponding value for a byte, it will return an error with the byte that failed.
-fn create_oracle(buf : &[u8]) -> Result<HashMap<u8, u32>,u8> {
-	let mut oracle : HashMap<u8, u32> = HashMap::new();
-	
-	for i in 0..256 {
-		let byte = i as u8;
-		let buflen : u32 = buf.len() as u32;
-		
-		for offset in 0..buflen {
-			let current_value = buf[offset as usize];
-			if current_value == byte {
-				oracle.insert(byte, offset);
-				break;
-			}
+use std::path::PathBuf;
+use std::process;
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+
+use rayon::prelude::*;
+
+use metastego::{MetastegError, Container, EncodeOptions, Format, DecodeOptions, Encoder, Header, WIDTHS, build_partial_oracle, encode_optimized, extension_for, decode_bytes, decode_with_report, decode_unverified, image_offsets, sha256, transcode};
+use metastego::header::{FLAG_PERMUTED, FLAG_COMPRESSED_OFFSETS, FLAG_COMPRESSED_PAYLOAD, FLAG_ARCHIVE, parse_header};
+use metastego::archive::{self, Entry};
+use metastego::audit::{Manifest, parse_hex};
+use metastego::bundle::{bundle, unbundle};
+use metastego::package::{PackageFormat, metadata, metadata_image_hash, package, unpackage};
+use metastego::rng::RngAlgorithm;
+use metastego::sliding::SlidingWindow;
+use metastego::chunk;
+use metastego::disguise::Disguise;
+use metastego::region::Region;
+use metastego::transform::{Transform, transform_image};
+use metastego::oracle::{count_occurrences, find_missing};
+use metastego::compress::deflate;
+
+// Options that can follow the positional arguments on the command line.
+struct Options {
+	encode: EncodeOptions,
+	decode: DecodeOptions,
+	histogram: bool,
+	all: bool,
+	verbose: bool,
+	count_only: bool,
+	time: bool,
+	teach: Option<String>,
+	checkpoint: bool,
+	resume: bool,
+	check_image: bool,
+	entry: Option<String>,
+	optimize: bool,
+	report_missing: bool,
+	auto_ext: bool,
+	deterministic: bool,
+	bundle: bool,
+	chunk_size: Option<usize>,
+	audit: Option<String>,
+	audit_key: Option<String>,
+	audit_public_key: Option<String>,
+	window_advance: Option<u32>,
+	max_image_bytes: Option<u64>,
+	max_payload_bytes: Option<u64>,
+	package: Option<PackageFormat>,
+	profile: Option<String>
+}
+
+fn parse_options(args: &[String]) -> Result<Options,String> {
+	let mut options = Options { encode: EncodeOptions::default(), decode: DecodeOptions::default(), histogram: false, all: false, verbose: false, count_only: false, time: false, teach: None, checkpoint: false, resume: false, check_image: false, entry: None, optimize: false, report_missing: false, auto_ext: false, deterministic: false, bundle: false, chunk_size: None, audit: None, audit_key: None, audit_public_key: None, window_advance: None, max_image_bytes: None, max_payload_bytes: None, package: None, profile: None };
+	let mut rng_given = false;
+	let mut i = 0;
+	while i < args.len() {
+		match args[i].as_str() {
+			"--max-offset" => {
+				let value = match args.get(i + 1) {
+					Some(x) => x,
+					None => return Err("--max-offset requires a value".to_string())
+				};
+				options.encode.max_offset = match value.parse::<u32>() {
+					Ok(x) => Some(x),
+					Err(_) => return Err(format!("Invalid value for --max-offset: '{}'", value))
+				};
+				i += 2;
+			},
+			"--max-image-bytes" | "--max-payload-bytes" => {
+				let value = match args.get(i + 1) {
+					Some(x) => x,
+					None => return Err(format!("{} requires a value", args[i]))
+				};
+				let limit = match value.parse::<u64>() {
+					Ok(x) => Some(x),
+					Err(_) => return Err(format!("Invalid value for {}: '{}'", args[i], value))
+				};
+				match args[i].as_str() {
+					"--max-image-bytes" => options.max_image_bytes = limit,
+					_ => options.max_payload_bytes = limit
+				}
+				i += 2;
+			},
+			"--sliding-window" | "--window-advance" => {
+				let value = match args.get(i + 1) {
+					Some(x) => x,
+					None => return Err(format!("{} requires a value", args[i]))
+				};
+				let parsed = match value.parse::<u32>() {
+					Ok(x) if x > 0 || args[i] == "--window-advance" => x,
+					_ => return Err(format!("Invalid value for {}: '{}'", args[i], value))
+				};
+				match args[i].as_str() {
+					"--sliding-window" => options.encode.sliding_window = Some(SlidingWindow { size: parsed, advance: 1 }),
+					_ => options.window_advance = Some(parsed)
+				}
+				i += 2;
+			},
+			"--width" => {
+				let value = match args.get(i + 1) {
+					Some(x) => x,
+					None => return Err("--width requires a value".to_string())
+				};
+				options.encode.width = match value.parse::<u8>() {
+					Ok(x) if WIDTHS.contains(&x) => x,
+					_ => return Err(format!("Invalid value for --width: '{}' (expected one of {:?})", value, WIDTHS))
+				};
+				i += 2;
+			},
+			"--permute" => {
+				let value = match args.get(i + 1) {
+					Some(x) => x,
+					None => return Err("--permute requires a key".to_string())
+				};
+				options.encode.permute_key = Some(value.to_string());
+				options.decode.permute_key = Some(value.to_string());
+				i += 2;
+			},
+			"--format" => {
+				let value = match args.get(i + 1) {
+					Some(x) => x,
+					None => return Err("--format requires a value".to_string())
+				};
+				options.encode.format = match Format::from_name(value) {
+					Some(x) => x,
+					None => return Err(format!("Invalid value for --format: '{}' (expected fixed, varint, planar or dictionary)", value))
+				};
+				i += 2;
+			},
+			"--seed" => {
+				let value = match args.get(i + 1) {
+					Some(x) => x,
+					None => return Err("--seed requires a value".to_string())
+				};
+				options.encode.seed = Some(value.to_string());
+				i += 2;
+			},
+			"--rng" => {
+				let value = match args.get(i + 1) {
+					Some(x) => x,
+					None => return Err("--rng requires a value".to_string())
+				};
+				options.encode.rng = match RngAlgorithm::from_name(value) {
+					Some(x) => x,
+					None => return Err(format!("Invalid value for --rng: '{}' (expected chacha20 or pcg)", value))
+				};
+				rng_given = true;
+				i += 2;
+			},
+			"--limit-occurrences" => {
+				let value = match args.get(i + 1) {
+					Some(x) => x,
+					None => return Err("--limit-occurrences requires a value".to_string())
+				};
+				options.encode.limit_occurrences = match value.as_str() {
+					"none" => None,
+					_ => match value.parse::<usize>() {
+						Ok(x) if x > 0 => Some(x),
+						_ => return Err(format!("Invalid value for --limit-occurrences: '{}' (expected a positive number or 'none')", value))
+					}
+				};
+				i += 2;
+			},
+			"--min-occurrences" => {
+				let value = match args.get(i + 1) {
+					Some(x) => x,
+					None => return Err("--min-occurrences requires a value".to_string())
+				};
+				options.encode.min_occurrences = match value.parse::<u64>() {
+					Ok(x) if x > 0 => Some(x),
+					_ => return Err(format!("Invalid value for --min-occurrences: '{}' (expected a positive number)", value))
+				};
+				i += 2;
+			},
+			"--block-size" => {
+				let value = match args.get(i + 1) {
+					Some(x) => x,
+					None => return Err("--block-size requires a value".to_string())
+				};
+				options.encode.block_size = match value.parse::<usize>() {
+					Ok(x) if x > 0 => Some(x),
+					_ => return Err(format!("Invalid value for --block-size: '{}'", value))
+				};
+				i += 2;
+			},
+			"--chunk-size" => {
+				let value = match args.get(i + 1) {
+					Some(x) => x,
+					None => return Err("--chunk-size requires a value".to_string())
+				};
+				options.chunk_size = match value.parse::<usize>() {
+					Ok(x) if x >= chunk::MIN_AVERAGE => Some(x),
+					_ => return Err(format!("Invalid value for --chunk-size: '{}' (expected at least {})", value, chunk::MIN_AVERAGE))
+				};
+				i += 2;
+			},
+			"--image-transform" => {
+				let value = match args.get(i + 1) {
+					Some(x) => x,
+					None => return Err("--image-transform requires a value".to_string())
+				};
+				options.encode.transform = match Transform::from_name(value) {
+					Some(x) => Some(x),
+					None => return Err(format!("Invalid value for --image-transform: '{}' (expected grayscale or downsample)", value))
+				};
+				i += 2;
+			},
+			"--mac-key" => {
+				let value = match args.get(i + 1) {
+					Some(x) => x,
+					None => return Err("--mac-key requires a key".to_string())
+				};
+				options.encode.mac_key = Some(value.to_string());
+				options.decode.mac_key = Some(value.to_string());
+				i += 2;
+			},
+			"--avoid-bytes" => {
+				let value = match args.get(i + 1) {
+					Some(x) => x,
+					None => return Err("--avoid-bytes requires a list of bytes".to_string())
+				};
+				options.encode.avoid_bytes = match parse_hex_list(value) {
+					Some(x) => x,
+					None => return Err(format!("Invalid value for --avoid-bytes: '{}' (expected comma-separated hex bytes, e.g. 00,0a,0d)", value))
+				};
+				i += 2;
+			},
+			"--content-type" => {
+				let value = match args.get(i + 1) {
+					Some(x) => x,
+					None => return Err("--content-type requires a MIME type or 'auto'".to_string())
+				};
+				if value == "auto" {
+					options.encode.sniff_content_type = true;
+				} else {
+					options.encode.content_type = Some(value.to_string());
+				}
+				i += 2;
+			},
+			"--regions" => {
+				let value = match args.get(i + 1) {
+					Some(x) => x,
+					None => return Err("--regions requires a list of regions".to_string())
+				};
+				options.encode.regions.extend(parse_regions(value.split(','))?);
+				i += 2;
+			},
+			"--regions-file" => {
+				let value = match args.get(i + 1) {
+					Some(x) => x,
+					None => return Err("--regions-file requires a path".to_string())
+				};
+				let spec = match fs::read_to_string(value) {
+					Ok(x) => x,
+					Err(e) => return Err(format!("Failed to read regions file '{}': {}", value, e))
+				};
+				// One region per line, ignoring blank lines and comments.
+				let lines = spec.lines().map(|line| line.split('#').next().unwrap().trim()).filter(|line| !line.is_empty());
+				options.encode.regions.extend(parse_regions(lines)?);
+				i += 2;
+			},
+			"--disguise" => {
+				let value = match args.get(i + 1) {
+					Some(x) => x,
+					None => return Err("--disguise requires a file type".to_string())
+				};
+				options.encode.disguise = match Disguise::from_name(value) {
+					Some(x) => Some(x),
+					None => return Err(format!("Invalid value for --disguise: '{}' (expected png, pdf or zip)", value))
+				};
+				i += 2;
+			},
+			"--expect-width" => {
+				let value = match args.get(i + 1) {
+					Some(x) => x,
+					None => return Err("--expect-width requires a value".to_string())
+				};
+				options.decode.expect_width = match value.parse::<u8>() {
+					Ok(x) if WIDTHS.contains(&x) => Some(x),
+					_ => return Err(format!("Invalid value for --expect-width: '{}' (expected one of {:?})", value, WIDTHS))
+				};
+				i += 2;
+			},
+			"--checksum" => {
+				options.encode.checksum = true;
+				i += 1;
+			},
+			"--fingerprint" => {
+				options.encode.fingerprint = true;
+				i += 1;
+			},
+			"--image-list" => {
+				options.encode.image_list = true;
+				i += 1;
+			},
+			"--payload-offset" | "--payload-length" => {
+				let value = match args.get(i + 1) {
+					Some(x) => x,
+					None => return Err(format!("{} requires a value", args[i]))
+				};
+				let parsed = match value.parse::<u64>() {
+					Ok(x) => Some(x),
+					Err(_) => return Err(format!("Invalid value for {}: '{}'", args[i], value))
+				};
+				if args[i] == "--payload-offset" {
+					options.encode.payload_offset = parsed;
+				} else {
+					options.encode.payload_length = parsed;
+				}
+				i += 2;
+			},
+			"--compress" => {
+				options.encode.compress_offsets = true;
+				i += 1;
+			},
+			"--compress-payload" => {
+				options.encode.compress_payload = true;
+				i += 1;
+			},
+			"--verbose" => {
+				options.verbose = true;
+				i += 1;
+			},
+			"--escape" => {
+				options.encode.escape = true;
+				i += 1;
+			},
+			"--monotonic" => {
+				options.encode.monotonic = true;
+				i += 1;
+			},
+			"--profile" => {
+				let value = match args.get(i + 1) {
+					Some(x) => x,
+					None => return Err("--profile requires a path for the trace file".to_string())
+				};
+				if !cfg!(feature = "profile") {
+					return Err("--profile needs metastego to be built with the profile feature (cargo build --features profile)".to_string());
+				}
+				options.profile = Some(value.to_string());
+				i += 2;
+			},
+			"--teach" => {
+				let value = match args.get(i + 1) {
+					Some(x) => x,
+					None => return Err("--teach requires a path for the mapping file".to_string())
+				};
+				options.teach = Some(value.to_string());
+				i += 2;
+			},
+			"--audit" | "--audit-key" | "--audit-public-key" => {
+				let value = match args.get(i + 1) {
+					Some(x) => x.to_string(),
+					None => return Err(format!("{} requires a path", args[i]))
+				};
+				match args[i].as_str() {
+					"--audit" => options.audit = Some(value),
+					"--audit-key" => options.audit_key = Some(value),
+					_ => options.audit_public_key = Some(value)
+				}
+				i += 2;
+			},
+			"--entry" => {
+				let value = match args.get(i + 1) {
+					Some(x) => x,
+					None => return Err("--entry requires an entry name".to_string())
+				};
+				options.entry = Some(value.to_string());
+				i += 2;
+			},
+			"--report-missing" => {
+				options.report_missing = true;
+				i += 1;
+			},
+			"--strict-offsets" => {
+				options.encode.record_image_length = true;
+				options.decode.strict_offsets = true;
+				i += 1;
+			},
+			"--auto-ext" => {
+				options.auto_ext = true;
+				i += 1;
+			},
+			"--optimize" => {
+				options.optimize = true;
+				i += 1;
+			},
+			"--check-image" => {
+				options.check_image = true;
+				i += 1;
+			},
+			"--checkpoint" => {
+				options.checkpoint = true;
+				i += 1;
+			},
+			"--resume" => {
+				options.resume = true;
+				i += 1;
+			},
+			"--time" => {
+				options.time = true;
+				i += 1;
+			},
+			"--count-only" => {
+				options.count_only = true;
+				i += 1;
+			},
+			"--all" => {
+				options.all = true;
+				i += 1;
+			},
+			"--histogram" => {
+				options.histogram = true;
+				i += 1;
+			},
+			"--archive" => {
+				// The format is only needed to encode. Decoding works it out from the package itself.
+				match args.get(i + 1).and_then(|x| PackageFormat::from_name(x)) {
+					Some(format) => {
+						options.package = Some(format);
+						i += 2;
+					},
+					None if args.get(i + 1).is_none_or(|x| x.starts_with("--")) => {
+						options.package = Some(PackageFormat::Tar);
+						i += 1;
+					},
+					None => return Err(format!("Invalid value for --archive: '{}' (expected tar or zip)", args[i + 1]))
+				}
+			},
+			"--bundle" => {
+				options.bundle = true;
+				i += 1;
+			},
+			"--deterministic" => {
+				options.deterministic = true;
+				i += 1;
+			},
+			other => return Err(format!("Unknown option: '{}'", other))
 		}
-		if !oracle.contains_key(&byte) {
-			return Err(byte);
+	}
+	// Everything else is already a pure function of the payload, image and options, but the default generator could change in a later version.
+	if options.deterministic && options.encode.seed.is_some() && !rng_given {
+		return Err("--deterministic requires --rng to be given along with --seed, so the output doesn't depend on the default generator".to_string());
+	}
+	match (&mut options.encode.sliding_window, options.window_advance) {
+		(Some(window), Some(advance)) => window.advance = advance,
+		(None, Some(_)) => return Err("--window-advance requires --sliding-window".to_string()),
+		_ => ()
+	}
+	if options.audit_key.is_some() && options.audit.is_none() {
+		return Err("--audit-key requires --audit".to_string());
+	}
+	// With a seed the chunks replace the blocks offsets are picked for; a checkpointed encode records a checkpoint after each one instead.
+	if let Some(average) = options.chunk_size {
+		if options.encode.seed.is_some() {
+			options.encode.chunk_average = Some(average);
+		} else if !options.checkpoint && !options.resume {
+			return Err("--chunk-size requires --seed or --checkpoint".to_string());
 		}
 	}
-	
-	Ok(oracle)
+	Ok(options)
 }
 
-// Use an oracle to encode a payload metasteganographically.
-// If it fails to translate a byte from the payload, it will return an error with the byte that failed.
-fn metasteg_encode(payload: &[u8], oracle: &HashMap<u8, u32>) -> Result<Vec<u32>,u8> {
-	let mut encoded : Vec<u32> = Vec::new();
-	for byte in payload {
-		let encoded_offset = match oracle.get(byte) {
-			Some(b) => *b,
-			None => return Err(*byte)
-		};
-		encoded.push(encoded_offset);
+// Parse a comma-separated list of hex bytes, each with an optional 0x prefix.
+fn parse_hex_list(value: &str) -> Option<Vec<u8>> {
+	value.split(',').map(|x| {
+		let digits = x.trim().trim_start_matches("0x");
+		u8::from_str_radix(digits, 16).ok()
+	}).collect()
+}
+
+fn parse_regions<'a>(specs: impl Iterator<Item = &'a str>) -> Result<Vec<Region>,String> {
+	specs.map(|spec| match Region::parse(spec) {
+		Some(x) => Ok(x),
+		None => Err(format!("Invalid region: '{}' (expected <hex values>:<start offset>-<end offset>, e.g. 00-7f:0-4096)", spec))
+	}).collect()
+}
+
+// Start recording tracing spans to a Chrome trace at the path, which is written out when the guard is dropped.
+// The trace can be opened in chrome://tracing or Perfetto, which show the spans as a flame graph.
+#[cfg(feature = "profile")]
+fn start_profile(path: &str) -> tracing_chrome::FlushGuard {
+	use tracing_subscriber::prelude::*;
+	let (layer, guard) = tracing_chrome::ChromeLayerBuilder::new().file(path).include_args(true).build();
+	tracing_subscriber::registry().with(layer).init();
+	guard
+}
+
+// Without the profile feature, --profile is refused when the options are parsed, so there is never a trace to start.
+#[cfg(not(feature = "profile"))]
+fn start_profile(_path: &str) {}
+
+// Times the phases of a command, printing each one to stderr as it finishes if timing is enabled.
+struct Timer {
+	enabled: bool,
+	start: Instant
+}
+
+impl Timer {
+	fn new(enabled: bool) -> Timer {
+		Timer { enabled, start: Instant::now() }
+	}
+
+	// Report the time since the previous phase finished (or the timer was created) and start timing the next one.
+	fn phase(&mut self, name: &str) {
+		if self.enabled {
+			eprintln!("{}: {:.3} ms", name, self.start.elapsed().as_secs_f64() * 1000.0);
+		}
+		self.start = Instant::now();
 	}
-	Ok(encoded)
 }
 
-// Use the original buffer to decode a payload metasteganographically.
-// If it fails to translate an offset from the payload, it will return an error with the offset that failed.
-fn metasteg_decode(payload: &[u32], buf: &[u8]) -> Result<Vec<u8>,u32> {
-	let mut decoded : Vec<u8> = Vec::new();
-	for offset in payload {
-		if (*offset as usize) > buf.len() {
-			return Err(*offset);
+// Refuse a file before reading it if it is larger than the limit given with the flag, if there is one.
+// This protects against a mistyped path pointing at something huge, which would otherwise be read into memory in full.
+fn check_file_size(kind: &str, path: &str, limit: Option<u64>, flag: &str) -> Result<(),MetastegError> {
+	let limit = match limit {
+		Some(x) => x,
+		None => return Ok(())
+	};
+	let length = fs::metadata(path).map_err(|e| MetastegError::io_read(kind, path, e))?.len();
+	if length > limit {
+		return Err(MetastegError::io_read(kind, path, std::io::Error::new(std::io::ErrorKind::FileTooLarge, format!("{} bytes is over the {} limit of {}", length, flag, limit))));
+	}
+	Ok(())
+}
+
+// Read an image, checking it against --max-image-bytes first.
+fn read_image(image_path: &str, options: &Options) -> Result<Vec<u8>,MetastegError> {
+	#[cfg(feature = "profile")]
+	let _span = tracing::info_span!("read").entered();
+	check_file_size("image", image_path, options.max_image_bytes, "--max-image-bytes")?;
+	fs::read(image_path).map_err(|e| MetastegError::io_read("image", image_path, e))
+}
+
+// Read a container to decode.
+fn read_container(input_path: &str) -> Result<Vec<u8>,MetastegError> {
+	#[cfg(feature = "profile")]
+	let _span = tracing::info_span!("read").entered();
+	fs::read(input_path).map_err(|e| MetastegError::io_read("container", input_path, e))
+}
+
+// Write one of the files a command produces.
+fn write_file(kind: &str, path: &str, data: &[u8]) -> Result<(),MetastegError> {
+	#[cfg(feature = "profile")]
+	let _span = tracing::info_span!("write").entered();
+	fs::write(path, data).map_err(|e| MetastegError::io_write(kind, path, e))
+}
+
+// Read the payload to encode. If it is a directory, its files are packed into an archive.
+fn read_payload(input_path: &str, options: &Options) -> Result<Vec<u8>,MetastegError> {
+	#[cfg(feature = "profile")]
+	let _span = tracing::info_span!("read").entered();
+	if !options.encode.archive {
+		check_file_size("payload", input_path, options.max_payload_bytes, "--max-payload-bytes")?;
+		return fs::read(input_path).map_err(|e| MetastegError::io_read("payload", input_path, e));
+	}
+	let mut entries : Vec<Entry> = Vec::new();
+	let listing = fs::read_dir(input_path).map_err(|e| MetastegError::io_read("payload directory", input_path, e))?;
+	for dir_entry in listing {
+		let path = dir_entry.map_err(|e| MetastegError::io_read("payload directory", input_path, e))?.path();
+		if !path.is_file() {
+			continue;
 		}
-		let decoded_byte = buf[*offset as usize];
-		decoded.push(decoded_byte);
+		let name = path.file_name().unwrap().to_string_lossy().to_string();
+		// The limit applies to the directory as a whole, so each file only gets what the ones before it left.
+		let remaining = options.max_payload_bytes.map(|limit| limit.saturating_sub(entries.iter().map(|entry| entry.data.len() as u64).sum()));
+		check_file_size("payload", &path.to_string_lossy(), remaining, "--max-payload-bytes")?;
+		let data = fs::read(&path).map_err(|e| MetastegError::io_read("payload", &path.to_string_lossy(), e))?;
+		entries.push(Entry { name, data });
+	}
+	// Sort the entries so the same directory always produces the same container.
+	entries.sort_by(|a, b| a.name.cmp(&b.name));
+	archive::pack(&entries)
+}
+
+// Report every byte value in the payload that the image can't provide, and how often it occurs.
+// Unless escapes will take care of them, missing values stop the encode before anything is written.
+fn report_missing(payload: &[u8], image: &[u8], options: &Options) -> Result<(),MetastegError> {
+	let missing = find_missing(payload, &build_partial_oracle(image, &options.encode)?);
+	if missing.is_empty() {
+		println!("Every byte value in the payload can be encoded with the image");
+		return Ok(());
+	}
+	let positions : usize = missing.iter().map(|x| x.count).sum();
+	println!("{} byte values in the payload are missing from the image, at {} positions:", missing.len(), positions);
+	for x in &missing {
+		println!("\t0x{:02x}: {} positions (first at {})", x.byte, x.count, x.first);
+	}
+	if options.encode.escape {
+		return Ok(());
 	}
-	Ok(decoded)
+	println!("Pad the image with these values, use a different image, or encode with --escape");
+	Err(MetastegError::UnencodableByte(missing[0].byte))
 }
 
-fn encode_file(input_path: &str, output_path: &str, image_path: &str) -> Result<(),String> {
+// Build the encoder for an image, and check it can encode any payload if --check-image was given.
+// This must happen before the output file is created, so that an unusable image never leaves a partial output behind.
+fn prepare_encoder(image: &[u8], options: &Options) -> Result<Encoder,MetastegError> {
+	let encoder = Encoder::new(image, &options.encode)?;
+	if options.check_image {
+		encoder.check_coverage()?;
+	}
+	Ok(encoder)
+}
+
+fn encode_file(input_path: &str, output_path: &str, image_path: &str, options: &Options) -> Result<(),MetastegError> {
+	if options.bundle && options.package.is_some() {
+		return Err(MetastegError::UnsupportedFeature("--bundle can't be combined with --archive".to_string()));
+	}
+	if options.checkpoint || options.resume {
+		if options.bundle || options.package.is_some() {
+			return Err(MetastegError::UnsupportedFeature(format!("{} can't be combined with --checkpoint", if options.bundle { "--bundle" } else { "--archive" })));
+		}
+		encode_checkpointed(input_path, output_path, image_path, options)?;
+		return write_audit(input_path, output_path, image_path, options);
+	}
+	// Seeded and monotonic encodes don't use one offset per byte value, and a sliding window stores each one relative to a different base,
+	// so none of them has a single mapping to teach.
+	let untaught = [(options.encode.seed.is_some(), "--seed"), (options.encode.monotonic, "--monotonic"), (options.encode.sliding_window.is_some(), "--sliding-window")];
+	match untaught.iter().find(|(set, _)| *set) {
+		Some((_, flag)) if options.teach.is_some() => return Err(MetastegError::UnsupportedFeature(format!("--teach can't be combined with {}", flag))),
+		_ => ()
+	}
+	let mut timer = Timer::new(options.time);
 	// Read in the payload and the image used to encode it.
-	let payload : Vec<u8> = match fs::read(input_path) {
-		Ok(x) => x,
-		Err(e) => return Err(e.to_string())
-	};
-	let image : Vec<u8> = match fs::read(image_path) {
-		Ok(x) => x,
-		Err(e) => return Err(e.to_string())
+	let payload : Vec<u8> = read_payload(input_path, options)?;
+	let image : Vec<u8> = read_image(image_path, options)?;
+	timer.phase("read");
+	if options.report_missing {
+		report_missing(&payload, &image, options)?;
+	}
+	// Build the oracle once, then encode the payload with it. Nothing is written until both have succeeded.
+	let encoder = prepare_encoder(&image, options)?;
+	timer.phase("oracle");
+	let (container, report) = if options.optimize {
+		let (chosen, container) = encode_optimized(&payload, &image, &options.encode)?;
+		println!("Smallest container ({} bytes): --format {} --width {}{}{}", container.len(), chosen.format.name(), chosen.width,
+			if chosen.compress_offsets { " --compress" } else { "" },
+			if chosen.compress_payload { " --compress-payload" } else { "" });
+		// The report comes from encoding the winner again, which gives the same container.
+		let (_, report) = Encoder::new(&image, &chosen)?.encode_container_with_report(&payload)?;
+		(container, report)
+	} else {
+		encoder.encode_container_with_report(&payload)?
 	};
-	// Create an oracle from the image.
-	let oracle = match create_oracle(&image) {
-		Ok(x) => x,
-		Err(e) => return Err(format!("Failed to create oracle; could not produce an offset for value 0x{:02x}", e))
+	timer.phase("encode");
+	if options.verbose {
+		println!("Payload size: {} bytes", report.payload_len);
+		if options.encode.compress_payload {
+			println!("Compressed payload size: {} bytes", deflate(&payload).len());
+		}
+		println!("Offsets: {} ({} distinct), from an oracle covering {}/256 byte values", report.offsets, report.distinct_offsets, report.coverage);
+		println!("Container size: {} bytes", report.container_len);
+	}
+	// Write the container to a file, after the image if it is being bundled with it, or packaged with its metadata.
+	let output = match (options.bundle, options.package) {
+		(true, _) => bundle(&image, &container),
+		(false, Some(format)) => package(format, &container, &metadata(&container, &image)?)?,
+		(false, None) => container
 	};
-	// Encode the payload with the oracle.
-	let encoded_payload = match metasteg_encode(&payload, &oracle) {
-		Ok(x) => x,
-		Err(e) => return Err(format!("Failed to encode payload with oracle; failed on byte {}", e))
+	write_file("output", output_path, &output)?;
+	if let Some(teach_path) = &options.teach {
+		let mapping = teaching_mapping(&encoder, &payload, &transform_image(options.encode.transform, &image));
+		write_file("mapping", teach_path, mapping.as_bytes())?;
+	}
+	timer.phase("write");
+	write_audit(input_path, output_path, image_path, options)
+}
+
+// Write the --audit manifest for a finished encode, if one was asked for, signing it if there's a key.
+// The files are read back from disk, so the manifest describes what was actually written.
+fn write_audit(input_path: &str, output_path: &str, image_path: &str, options: &Options) -> Result<(),MetastegError> {
+	let manifest_path = match &options.audit {
+		Some(x) => x,
+		None => return Ok(())
 	};
-	// Write the encoded payload to a file.
-	let mut output = match fs::File::create(output_path) {
-		Ok(x) => x,
-		Err(e) => return Err(e.to_string())
+	let (payload, image, container) = read_audited(input_path, image_path, output_path, options)?;
+	let mut manifest = Manifest::new(&payload, &image, &container)?;
+	if let Some(key_path) = &options.audit_key {
+		manifest.sign(&read_key(key_path, "audit key")?);
+	}
+	fs::write(manifest_path, manifest.to_json()).map_err(|e| MetastegError::io_write("audit manifest", manifest_path, e))
+}
+
+// The payload, image and container an audit manifest describes.
+type AuditedFiles = (Vec<u8>, Vec<u8>, Vec<u8>);
+
+// Read the files an audit manifest describes. A bundled container is taken out of its bundle.
+fn read_audited(payload_path: &str, image_path: &str, container_path: &str, options: &Options) -> Result<AuditedFiles,MetastegError> {
+	let payload = read_payload(payload_path, options)?;
+	let image = read_image(image_path, options)?;
+	let mut container = fs::read(container_path).map_err(|e| MetastegError::io_read("container", container_path, e))?;
+	if options.bundle {
+		container = unbundle(&container)?.1.to_vec();
+	} else if options.package.is_some() {
+		container = unpackage(&container)?.0;
+	}
+	Ok((payload, image, container))
+}
+
+// Read a 32-byte Ed25519 key from a file holding it in hex.
+fn read_key(path: &str, kind: &str) -> Result<[u8;32],MetastegError> {
+	let text = fs::read_to_string(path).map_err(|e| MetastegError::io_read(kind, path, e))?;
+	match parse_hex::<32>(&text) {
+		Some(x) => Ok(x),
+		None => Err(MetastegError::io_read(kind, path, std::io::Error::new(std::io::ErrorKind::InvalidData, "expected 64 hex digits")))
+	}
+}
+
+// Check an audit manifest against the files it describes, and its signature if it has one.
+// Returns the public key the manifest was signed with.
+fn verify_audit(manifest_path: &str, payload_path: &str, image_path: &str, container_path: &str, options: &Options) -> Result<Option<[u8;32]>,MetastegError> {
+	let json = fs::read_to_string(manifest_path).map_err(|e| MetastegError::io_read("audit manifest", manifest_path, e))?;
+	let manifest = Manifest::parse(&json)?;
+	let public_key = match &options.audit_public_key {
+		Some(x) => Some(read_key(x, "public key")?),
+		None => None
 	};
-	for offset in encoded_payload {
-		let offset_bytes = offset.to_be_bytes();
-		match output.write_all(&offset_bytes) {
-			Ok(_) => (),
-			Err(e) => return Err(e.to_string())
+	let (payload, image, container) = read_audited(payload_path, image_path, container_path, options)?;
+	manifest.verify(&payload, &image, &container, public_key.as_ref())?;
+	Ok(manifest.signature.map(|(signer, _)| signer))
+}
+
+// How much of the payload is encoded between checkpoints.
+const CHECKPOINT_INTERVAL : usize = 1 << 20;
+
+// Read the next content-defined chunk of a payload, keeping whatever was read past its end in pending for the next call.
+// Enough is read ahead to hold the largest chunk, so the boundary found is the same as if the whole payload were in memory. The chunk is empty once the payload runs out.
+fn next_content_chunk(payload: &mut impl Read, pending: &mut Vec<u8>, average: usize) -> std::io::Result<Vec<u8>> {
+	let (_, max) = chunk::chunk_bounds(average);
+	payload.take(max.saturating_sub(pending.len()) as u64).read_to_end(pending)?;
+	let rest = pending.split_off(chunk::cut_point(pending, average));
+	Ok(std::mem::replace(pending, rest))
+}
+
+// The sidecar file recording how far a checkpointed encode got.
+fn checkpoint_path(output_path: &str) -> String {
+	format!("{}.checkpoint", output_path)
+}
+
+// Encode a payload as a stream, recording a checkpoint after each chunk so an interrupted encode can be resumed.
+// A checkpoint holds the number of payload bytes encoded and the length of the output at that point.
+// On resume, the output is cut back to the last checkpoint and encoding carries on from there. The checkpoint is removed once the encode finishes.
+fn encode_checkpointed(input_path: &str, output_path: &str, image_path: &str, options: &Options) -> Result<(),MetastegError> {
+	if options.encode.archive {
+		return Err(MetastegError::UnsupportedFeature("directories can't be encoded with checkpoints".to_string()));
+	}
+	let image : Vec<u8> = read_image(image_path, options)?;
+	let mut encoder = prepare_encoder(&image, options)?;
+	// update would refuse these options too, but only after the output had been created.
+	encoder.check_streamable()?;
+	check_file_size("payload", input_path, options.max_payload_bytes, "--max-payload-bytes")?;
+	let mut payload = fs::File::open(input_path).map_err(|e| MetastegError::io_read("payload", input_path, e))?;
+	let checkpoint = checkpoint_path(output_path);
+	let mut output;
+	let mut encoded : u64 = 0;
+	if options.resume {
+		let recorded = fs::read_to_string(&checkpoint).map_err(|e| MetastegError::io_read("checkpoint", &checkpoint, e))?;
+		let (payload_bytes, output_bytes) = match recorded.trim().split_once(' ').map(|(a, b)| (a.parse::<u64>(), b.parse::<u64>())) {
+			Some((Ok(a), Ok(b))) => (a, b),
+			_ => return Err(MetastegError::io_read("checkpoint", &checkpoint, std::io::Error::new(std::io::ErrorKind::InvalidData, "expected the payload and output byte counts")))
+		};
+		output = fs::OpenOptions::new().read(true).write(true).open(output_path).map_err(|e| MetastegError::io_read("output", output_path, e))?;
+		// The existing output must have been produced with the same image and options, or the two halves won't fit together.
+		let header = metastego::header::serialize_header(encoder.header())?;
+		let mut existing = vec![0u8; header.len()];
+		if output.read_exact(&mut existing).is_err() || existing != header || output_bytes < header.len() as u64 {
+			return Err(MetastegError::UnsupportedFeature(format!("'{}' wasn't encoded with the same image and options, so it can't be resumed", output_path)));
+		}
+		output.set_len(output_bytes).map_err(|e| MetastegError::io_write("output", output_path, e))?;
+		output.seek(SeekFrom::End(0)).map_err(|e| MetastegError::io_write("output", output_path, e))?;
+		payload.seek(SeekFrom::Start(payload_bytes)).map_err(|e| MetastegError::io_read("payload", input_path, e))?;
+		encoder.skip_header();
+		encoder.skip_payload(payload_bytes as usize);
+		encoded = payload_bytes;
+	} else {
+		output = fs::File::create(output_path).map_err(|e| MetastegError::io_write("output", output_path, e))?;
+	}
+	let mut chunk = vec![0u8; CHECKPOINT_INTERVAL];
+	let mut pending : Vec<u8> = Vec::new();
+	loop {
+		let length = match options.chunk_size {
+			Some(average) => {
+				chunk = next_content_chunk(&mut payload, &mut pending, average).map_err(|e| MetastegError::io_read("payload", input_path, e))?;
+				chunk.len()
+			},
+			None => payload.read(&mut chunk).map_err(|e| MetastegError::io_read("payload", input_path, e))?
 		};
+		if length == 0 {
+			break;
+		}
+		let serialized = encoder.update(&chunk[..length])?;
+		output.write_all(&serialized).map_err(|e| MetastegError::io_write("output", output_path, e))?;
+		// Make sure the offsets are on disk before the checkpoint says they are.
+		output.sync_data().map_err(|e| MetastegError::io_write("output", output_path, e))?;
+		encoded += length as u64;
+		let output_bytes = output.stream_position().map_err(|e| MetastegError::io_write("output", output_path, e))?;
+		let pending = format!("{}.tmp", checkpoint);
+		fs::write(&pending, format!("{} {}\n", encoded, output_bytes)).map_err(|e| MetastegError::io_write("checkpoint", &pending, e))?;
+		fs::rename(&pending, &checkpoint).map_err(|e| MetastegError::io_write("checkpoint", &checkpoint, e))?;
+	}
+	output.write_all(&encoder.finish()?).map_err(|e| MetastegError::io_write("output", output_path, e))?;
+	if fs::metadata(&checkpoint).is_ok() {
+		fs::remove_file(&checkpoint).map_err(|e| MetastegError::io_write("checkpoint", &checkpoint, e))?;
+	}
+	if options.verbose {
+		println!("Encoded {} payload bytes", encoded);
 	}
 	
 	Ok(())
 }
 
-fn decode_file(input_path: &str, output_path: &str, image_path: &str) -> Result<(),String> {
+// Describe the oracle an encoder uses in a form that can be followed by hand: every byte value, its offset,
+// and the bytes of the image around that offset. This gives away the whole oracle, so it's only for teaching.
+fn teaching_mapping(encoder: &Encoder, payload: &[u8], image: &[u8]) -> String {
+	let used : HashSet<u8> = payload.iter().copied().collect();
+	let header = encoder.header();
+	let mut mapping = String::new();
+	mapping.push_str("# Each payload byte is stored as the offset of a byte with the same value in the image.\n");
+	match Format::from_flags(header.flags) {
+		Format::Fixed => mapping.push_str(&format!("# Offsets are stored as {}-byte big-endian integers after the header.\n", header.width)),
+		Format::Varint => mapping.push_str("# Offsets are stored as LEB128 varints after the header.\n"),
+		Format::Planar => mapping.push_str(&format!("# Offsets are stored as {}-byte big-endian integers after the header, one byte plane at a time.\n", header.width)),
+		Format::Dictionary => mapping.push_str(&format!("# After the header is a 2-byte count and a dictionary of that many {}-byte big-endian offsets, then a 1-byte dictionary index for each payload byte.\n", header.width))
+	}
+	if header.flags & FLAG_PERMUTED != 0 {
+		mapping.push_str("# The offsets are permuted, so they appear in a scrambled order in the container.\n");
+	}
+	mapping.push_str("# Values marked * occur in the payload. The image bytes around each offset are shown, with the chosen byte in brackets.\n");
+	mapping.push_str("# value  offset  image bytes\n");
+	for (value, offset) in encoder.oracle() {
+		let offset = *offset as usize;
+		let before = hex(&image[offset.saturating_sub(4)..offset]);
+		let after = hex(&image[offset + 1..(offset + 5).min(image.len())]);
+		let marker = if used.contains(value) { '*' } else { ' ' };
+		mapping.push_str(&format!("{}0x{:02x}  {:>6}  {}[{:02x}]{}\n", marker, value, offset, before, image[offset], after));
+	}
+	mapping
+}
+
+// Returns the path the payload was written to, which --auto-ext may have added an extension to.
+// Without an image path, the input is a bundle holding both the image and the container.
+fn decode_file(input_path: &str, output_path: &str, image_path: Option<&str>, options: &Options) -> Result<String,MetastegError> {
+	let mut timer = Timer::new(options.time);
 	// Read in the encoded/serialized payload and the image used to encode it.
-	let serialized_payload : Vec<u8> = match fs::read(input_path) {
-		Ok(x) => x,
-		Err(e) => return Err(e.to_string())
-	};
-	let image : Vec<u8> = match fs::read(image_path) {
-		Ok(x) => x,
-		Err(e) => return Err(e.to_string())
+	let input : Vec<u8> = read_container(input_path)?;
+	let (container, image) = match image_path {
+		Some(image_path) if options.package.is_some() => {
+			let (container, metadata) = unpackage(&input)?;
+			let image = read_image(image_path, options)?;
+			// The metadata records the image's hash, so the wrong image is caught before decoding, as with --fingerprint.
+			if let Some(digest) = metadata.as_deref().map(metadata_image_hash).transpose()?.flatten() {
+				if sha256(&image) != digest {
+					return Err(MetastegError::ImageMismatch);
+				}
+			}
+			(container, image)
+		},
+		Some(image_path) => (input, read_image(image_path, options)?),
+		None => {
+			let (image, container) = unbundle(&input)?;
+			(container.to_vec(), image.to_vec())
+		}
 	};
-	// Check and deserialize the payload.
-	if serialized_payload.len() % 4 != 0 {
-		return Err(format!("Serialized payload has an invalid length: {}", serialized_payload.len()));
+	timer.phase("read");
+	// Decode the payload with the image.
+	let (decoded_payload, report) = decode_with_report(&container, &image, &options.decode)?;
+	timer.phase("decode");
+	if options.verbose {
+		println!("Container size: {} bytes", report.container_len);
+		println!("Offsets: {} ({} distinct)", report.offsets, report.distinct_offsets);
+		println!("Payload size: {} bytes", report.payload_len);
+	}
+	let (header, _) = parse_header(&container)?;
+	if header.flags & FLAG_ARCHIVE != 0 {
+		extract_archive(&decoded_payload, output_path, options)?;
+		timer.phase("write");
+		return Ok(output_path.to_string());
+	}
+	if options.entry.is_some() {
+		return Err(MetastegError::UnsupportedFeature("--entry needs a container that holds an archive".to_string()));
 	}
+	// Single payloads don't store a file name, so the content type (or failing that, the payload itself) is the only clue to an extension.
+	// A path that already has an extension is left alone.
+	let mut output_path = PathBuf::from(output_path);
+	if options.auto_ext && output_path.extension().is_none() {
+		if let Some(extension) = extension_for(header.content_type.as_deref(), &decoded_payload) {
+			output_path.set_extension(extension);
+		}
+	}
+	let output_path = output_path.to_string_lossy().to_string();
+	// Write the decoded payload to a file.
+	write_file("output", &output_path, &decoded_payload)?;
+	if let Header { content_type: Some(content_type), .. } = header {
+		println!("Payload content type: {}", content_type);
+	}
+	timer.phase("write");
 	
-	let mut payload : Vec<u32> = Vec::new();
-	let mut i = 0;
-	loop {
-		if i >= serialized_payload.len() { break; }
-		let current_offset_serialized : [u8;4] = serialized_payload[i..i+4].try_into().unwrap();
-		let current_offset = u32::from_be_bytes(current_offset_serialized);
-		payload.push(current_offset);
-		i += 4;
+	Ok(output_path)
+}
+
+// Unpack a decoded archive into a directory, creating it if need be. With --entry, only that entry is extracted.
+fn extract_archive(packed: &[u8], output_path: &str, options: &Options) -> Result<(),MetastegError> {
+	let entries = archive::unpack(packed)?;
+	if let Some(name) = &options.entry {
+		if !entries.iter().any(|(entry, _)| entry.name == *name) {
+			return Err(MetastegError::InvalidArchive(format!("there is no entry named '{}'", name)));
+		}
 	}
-	// Decode the payload with the image.
-	let decoded_payload = match metasteg_decode(&payload, &image) {
-		Ok(x) => x,
-		Err(e) => return Err(format!("Failed to decode payload with image; failure on offset {}", e))
+	fs::create_dir_all(output_path).map_err(|e| MetastegError::io_write("output directory", output_path, e))?;
+	for (entry, checksum) in entries {
+		if options.entry.as_ref().is_some_and(|name| *name != entry.name) {
+			continue;
+		}
+		// unpack has already rejected names that aren't plain file names, so this stays inside the directory.
+		let path = PathBuf::from(output_path).join(&entry.name);
+		write_file("output", &path.to_string_lossy(), &entry.data)?;
+		println!("Extracted '{}' ({} bytes, checksum {} verified)", entry.name, entry.data.len(), hex(&checksum));
+	}
+	
+	Ok(())
+}
+
+// Decode a container and report the length of the payload, without writing it anywhere.
+fn count_file(input_path: &str, image_path: &str, options: &Options) -> Result<usize,MetastegError> {
+	let mut timer = Timer::new(options.time);
+	let container : Vec<u8> = read_container(input_path)?;
+	let image : Vec<u8> = read_image(image_path, options)?;
+	timer.phase("read");
+	// Decoding in full checks every offset against the image (and the checksum, if there is one).
+	let decoded_payload = decode_bytes(&container, &image, &options.decode)?;
+	timer.phase("decode");
+	Ok(decoded_payload.len())
+}
+
+// Decode two containers with the same image and report how they differ, without writing anything.
+fn compare_files(path_a: &str, path_b: &str, image_path: &str, options: &Options) -> Result<(),MetastegError> {
+	let container_a : Vec<u8> = fs::read(path_a).map_err(|e| MetastegError::io_read("container", path_a, e))?;
+	let container_b : Vec<u8> = fs::read(path_b).map_err(|e| MetastegError::io_read("container", path_b, e))?;
+	let image : Vec<u8> = read_image(image_path, options)?;
+	// Compare the headers field by field. If either header is invalid, decoding it below reports why.
+	if let (Ok((header_a, _)), Ok((header_b, _))) = (parse_header(&container_a), parse_header(&container_b)) {
+		if header_a.width != header_b.width {
+			println!("Offset width differs: {} vs {}", header_a.width, header_b.width);
+		}
+		if header_a.flags != header_b.flags {
+			println!("Flags differ: 0x{:08x} vs 0x{:08x}", header_a.flags, header_b.flags);
+		}
+		if header_a.max_offset != header_b.max_offset {
+			println!("Offset window differs: {:?} vs {:?}", header_a.max_offset, header_b.max_offset);
+		}
+		if header_a.disguise != header_b.disguise {
+			println!("Disguise differs: {:?} vs {:?}", header_a.disguise, header_b.disguise);
+		}
+	}
+	// Compare the decoded payloads and the container sizes.
+	let payload_a = decode_bytes(&container_a, &image, &options.decode);
+	let payload_b = decode_bytes(&container_b, &image, &options.decode);
+	match (&payload_a, &payload_b) {
+		(Ok(a), Ok(b)) if a == b => println!("Both containers decode to the same payload ({} bytes)", a.len()),
+		(Ok(a), Ok(b)) => println!("The containers decode to different payloads ({} vs {} bytes)", a.len(), b.len()),
+		_ => {
+			if let Err(e) = &payload_a { println!("'{}' does not decode with '{}': {}", path_a, image_path, e); }
+			if let Err(e) = &payload_b { println!("'{}' does not decode with '{}': {}", path_b, image_path, e); }
+		}
+	}
+	let difference = container_b.len() as i64 - container_a.len() as i64;
+	println!("Container sizes: {} vs {} bytes ({:+} bytes)", container_a.len(), container_b.len(), difference);
+	
+	Ok(())
+}
+
+// Report how well an image covers the 256 byte values, and optionally how often each one occurs.
+fn analyze_file(image_path: &str, options: &Options) -> Result<(),MetastegError> {
+	let image : Vec<u8> = read_image(image_path, options)?;
+	// With --image-transform, analyze the image as the oracle would see it.
+	let image = transform_image(options.encode.transform, &image);
+	let counts = count_occurrences(&image);
+	let missing : Vec<String> = (0..256).filter(|i| counts[*i] == 0).map(|i| format!("0x{:02x}", i)).collect();
+	println!("Image size: {} bytes", image.len());
+	println!("Byte values present: {}/256", 256 - missing.len());
+	if !missing.is_empty() {
+		println!("Missing byte values: {}", missing.join(" "));
+	}
+	if options.histogram {
+		// Scale the bars so the most common value fills the full width.
+		let max_count = counts.iter().max().copied().unwrap_or(0).max(1);
+		for (value, count) in counts.iter().enumerate() {
+			let bar = "#".repeat(((*count as u128 * 40).div_ceil(max_count as u128)) as usize);
+			println!("0x{:02x} {:>10} {}", value, count, bar);
+		}
+	}
+	
+	Ok(())
+}
+
+// Try every file in a directory as the image for a container, reporting the first (or every) one that decodes it.
+// Candidates are checked against the image fingerprint if the container has one, or by decoding and verifying the checksum.
+fn find_image(container_path: &str, dir_path: &str, options: &Options) -> Result<(),MetastegError> {
+	let container : Vec<u8> = fs::read(container_path).map_err(|e| MetastegError::io_read("container", container_path, e))?;
+	let (header, _) = parse_header(&container)?;
+	if header.checksum.is_none() && header.fingerprint.is_none() {
+		return Err(MetastegError::UnsupportedFeature("the container has no checksum or image fingerprint to check candidate images against".to_string()));
+	}
+	let entries = fs::read_dir(dir_path).map_err(|e| MetastegError::io_read("image directory", dir_path, e))?;
+	let mut candidates : Vec<PathBuf> = entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).filter(|path| path.is_file()).collect();
+	candidates.sort();
+	
+	let decodes = |path: &&PathBuf| -> bool {
+		let image = match read_image(&path.to_string_lossy(), options) {
+			Ok(x) => x,
+			Err(_) => return false
+		};
+		match header.fingerprint {
+			Some(fingerprint) => sha256(&image) == fingerprint,
+			None => decode_bytes(&container, &image, &options.decode).is_ok()
+		}
 	};
-	// Write the decoded payload to a file.
-	match fs::write(output_path, decoded_payload) {
-		Ok(_) => (),
-		Err(e) => return Err(e.to_string())
+	let found : Vec<&PathBuf> = if options.all {
+		candidates.par_iter().filter(decodes).collect()
+	} else {
+		candidates.par_iter().find_first(decodes).into_iter().collect()
 	};
 	
+	if found.is_empty() {
+		println!("None of the {} files in '{}' decode '{}'", candidates.len(), dir_path, container_path);
+	}
+	for path in found {
+		println!("'{}' decodes '{}'", path.display(), container_path);
+	}
+	
+	Ok(())
+}
+
+// Print every field of a container header.
+fn print_header(header: &Header) {
+	println!("Offset width: {} bytes", header.width);
+	println!("Flags: 0x{:08x}", header.flags);
+	println!("Permuted: {}", header.flags & FLAG_PERMUTED != 0);
+	println!("Compressed offsets: {}", header.flags & FLAG_COMPRESSED_OFFSETS != 0);
+	println!("Compressed payload: {}", header.flags & FLAG_COMPRESSED_PAYLOAD != 0);
+	println!("Archive: {}", header.flags & FLAG_ARCHIVE != 0);
+	println!("Format: {}", Format::from_flags(header.flags).name());
+	if let Some(max_offset) = header.max_offset {
+		println!("Offset window: below {}", max_offset);
+	}
+	if let Some(disguise) = header.disguise {
+		println!("Disguise: {}", disguise.name());
+	}
+	if let Some(sentinel) = header.sentinel {
+		println!("Escape sentinel: {}", sentinel);
+	}
+	if let Some((offset, length)) = header.payload_range {
+		println!("Payload range: {} bytes at offset {}", length, offset);
+	}
+	if let Some(checksum) = header.checksum {
+		println!("Checksum: {}", hex(&checksum));
+	}
+	if let Some(fingerprint) = header.fingerprint {
+		println!("Image fingerprint: {}", hex(&fingerprint));
+	}
+	for region in &header.regions {
+		println!("Region: values 0x{:02x}-0x{:02x} from offsets {} to {}", region.values.start(), region.values.end(), region.offsets.start, region.offsets.end);
+	}
+	if let Some(transform) = header.transform {
+		println!("Image transform: {}", transform.name());
+	}
+	if let Some(image_length) = header.image_length {
+		println!("Image length: {} bytes", image_length);
+	}
+	if let Some(window) = header.sliding_window {
+		println!("Sliding window: {} bytes, advancing {} per payload byte", window.size, window.advance);
+	}
+	if let Some(content_type) = &header.content_type {
+		println!("Content type: {}", content_type);
+	}
+	if let Some(mac) = header.mac {
+		println!("MAC: {}", hex(&mac));
+	}
+	if let Some(images) = &header.images {
+		for (i, (hash, length)) in images.iter().enumerate() {
+			println!("Image {} of {}: {} ({} bytes)", i + 1, images.len(), hex(hash), length);
+		}
+	}
+}
+
+fn hex(bytes: &[u8]) -> String {
+	bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Decode a container in memory and report everything about it: header, checksum status and offset distribution.
+fn stats_file(container_path: &str, image_path: &str, options: &Options) -> Result<(),MetastegError> {
+	let container : Vec<u8> = fs::read(container_path).map_err(|e| MetastegError::io_read("container", container_path, e))?;
+	let image : Vec<u8> = read_image(image_path, options)?;
+	let Container { header, offsets } = Container::parse_with(&container, &options.decode)?;
+	println!("Container size: {} bytes", container.len());
+	print_header(&header);
+	
+	// Decode without verifying, so the verification status can be reported rather than failing.
+	let decoded = decode_unverified(&header, &offsets, &image)?;
+	println!("Image size: {} bytes", image.len());
+	if let Some(fingerprint) = header.fingerprint {
+		println!("Image fingerprint status: {}", if sha256(&image) == fingerprint { "matches" } else { "MISMATCH" });
+	}
+	println!("Decoded payload length: {} bytes", decoded.len());
+	match header.checksum {
+		Some(checksum) if sha256(&decoded) == checksum => println!("Checksum status: verified"),
+		Some(_) => println!("Checksum status: MISMATCH"),
+		None => println!("Checksum status: not present")
+	}
+	
+	let real_offsets = image_offsets(&header, &offsets);
+	// Each escaped literal takes two offsets, the sentinel and the literal, and only leaves real offsets behind.
+	println!("Offsets: {} ({} escaped literals)", offsets.len(), (offsets.len() - real_offsets.len()) / 2);
+	if let (Some(min), Some(max)) = (real_offsets.iter().min(), real_offsets.iter().max()) {
+		let mean = real_offsets.iter().map(|offset| *offset as f64).sum::<f64>() / real_offsets.len() as f64;
+		println!("Offset range: {} to {} (mean {:.1}){}", min, max, mean, window_note(&header));
+	}
+	let distinct : HashSet<u32> = real_offsets.iter().copied().collect();
+	println!("Distinct offsets: {}{}", distinct.len(), window_note(&header));
+	
 	Ok(())
 }
 
+// Encode a payload with an image in memory and report how well the image serves as a cover for it.
+// Returns whether the payload could be encoded.
+fn bench_image(payload_path: &str, image_path: &str, options: &Options) -> Result<bool,MetastegError> {
+	check_file_size("payload", payload_path, options.max_payload_bytes, "--max-payload-bytes")?;
+	let payload : Vec<u8> = fs::read(payload_path).map_err(|e| MetastegError::io_read("payload", payload_path, e))?;
+	let image : Vec<u8> = read_image(image_path, options)?;
+	let missing = find_missing(&payload, &build_partial_oracle(&image, &options.encode)?);
+	let container = match Encoder::new(&image, &options.encode).and_then(|encoder| encoder.encode_container(&payload)) {
+		Ok(x) => x,
+		Err(e) => {
+			println!("Fully encodes: no ({})", e);
+			if !missing.is_empty() {
+				println!("Payload byte values missing from the image: {}", missing.len());
+			}
+			return Ok(false);
+		}
+	};
+	let Container { header, offsets } = Container::parse_with(&container, &options.decode)?;
+	let real_offsets = image_offsets(&header, &offsets);
+	println!("Fully encodes: {}", if missing.is_empty() { "yes" } else { "yes, with escaped literals" });
+	println!("Container size: {} bytes for a {}-byte payload", container.len(), payload.len());
+	// The Shannon entropy of the offsets, treating each distinct offset as a symbol.
+	let mut counts : HashMap<u32, usize> = HashMap::new();
+	for offset in &real_offsets {
+		*counts.entry(*offset).or_default() += 1;
+	}
+	let total = real_offsets.len() as f64;
+	let entropy : f64 = counts.values().map(|count| {
+		let p = *count as f64 / total;
+		// Written as log2(1/p) so a single distinct offset gives 0 rather than -0.
+		p * (1.0 / p).log2()
+	}).sum();
+	println!("Offset entropy: {:.3} bits per offset ({} distinct offsets)", entropy, counts.len());
+	if let (Some(min), Some(max)) = (real_offsets.iter().min(), real_offsets.iter().max()) {
+		println!("Offset range: {} to {}{}", min, max, window_note(&header));
+	}
+	
+	Ok(true)
+}
+
+// A note for offsets printed as they are stored, which with a sliding window aren't positions in the image.
+fn window_note(header: &Header) -> &'static str {
+	match header.sliding_window {
+		Some(_) => ", relative to the sliding window",
+		None => ""
+	}
+}
+
+// Re-encode a container for a different image, so the cover can be changed without the payload file.
+// The settings are taken from the container's header, and the keys from the options.
+fn transcode_file(container_path: &str, old_image_path: &str, new_image_path: &str, output_path: &str, options: &Options) -> Result<(),MetastegError> {
+	let container = read_container(container_path)?;
+	let old_image = read_image(old_image_path, options)?;
+	let new_image = read_image(new_image_path, options)?;
+	let transcoded = transcode(&container, &old_image, &new_image, &options.decode)?;
+	write_file("output", output_path, &transcoded)
+}
+
+// Truncate a container to a whole number of offsets, dropping stray trailing bytes, and write it out.
+// Returns the number of bytes dropped.
+fn repair_file(input_path: &str, output_path: &str) -> Result<usize,MetastegError> {
+	let mut container : Vec<u8> = fs::read(input_path).map_err(|e| MetastegError::io_read("container", input_path, e))?;
+	let (header, body_start) = parse_header(&container)?;
+	// Only fixed-width offsets stored as they are can be realigned by cutting bytes off the end.
+	if header.flags & FLAG_COMPRESSED_OFFSETS != 0 || Format::from_flags(header.flags) != Format::Fixed {
+		return Err(MetastegError::UnsupportedFeature("only uncompressed fixed-width containers can be repaired".to_string()));
+	}
+	let dropped = (container.len() - body_start) % header.width as usize;
+	container.truncate(container.len() - dropped);
+	// More than half an offset's worth of extra bytes looks more like a lost or damaged offset than stray bytes.
+	if dropped * 2 > header.width as usize {
+		println!("Warning: dropped {} of the {} bytes in an offset; the container may be truncated or corrupted rather than padded", dropped, header.width);
+	}
+	if dropped > 0 && header.mac.is_some() {
+		println!("Warning: the container has a MAC, which won't match once it has been changed");
+	}
+	fs::write(output_path, container).map_err(|e| MetastegError::io_write("output", output_path, e))?;
+	
+	Ok(dropped)
+}
+
 fn usage() {
 	let args : Vec<String> = env::args().collect();
-	println!("USAGE: {} [encode|decode]", args[0]);
-	println!("\tencode <path to plaintext payload> <output path> <image to use>");
-	println!("\tdecode <path to encoded payload> <output path> <image to use>");
+	println!("USAGE: {} [encode|decode|compare|analyze|find-image|stats|repair|bench-image|verify-audit|transcode-image]", args[0]);
+	println!("\tencode <path to plaintext payload> <output path> <image to use> [options]");
+	println!("\tdecode <path to encoded payload> <output path> <image to use> [options]");
+	println!("\tdecode <path to encoded payload> <image to use> --count-only [options]");
+	println!("\tdecode <path to bundle> <output path> --bundle [options]");
+	println!("\tcompare <path to encoded payload> <path to another encoded payload> <image to use> [options]");
+	println!("\tanalyze <image to use> [options]");
+	println!("\tfind-image <path to encoded payload> <directory of candidate images> [options]");
+	println!("\tstats <path to encoded payload> <image to use> [options]");
+	println!("\trepair <path to encoded payload> <output path>");
+	println!("\tbench-image <path to plaintext payload> <image to use> [encode options]");
+	println!("\ttranscode-image <path to encoded payload> <image it was encoded with> <new image to use> <output path> [options]");
+	println!("\tverify-audit <manifest> <path to plaintext payload> <image to use> <path to encoded payload> [options]");
+	println!();
+	println!("ENCODE OPTIONS:");
+	println!("\t--max-offset <n>\tonly use offsets below n in the image");
+	println!("\t--width <1|2|4|8>\tnumber of bytes used to store each offset (default 4)");
+	println!("\t--format <fixed|varint|planar|dictionary>\thow the offsets are serialized (default fixed)");
+	println!("\t--permute <key>\t\tstore the offsets in an order scrambled by the key");
+	println!("\t--disguise <png|pdf|zip>\tmake the output start like a file of that type");
+	println!("\t--checksum\t\trecord a checksum of the payload to detect decoding with the wrong image");
+	println!("\t--fingerprint\t\trecord a hash of the image so it can be identified");
+	println!("\t--image-list\t\trecord the hash and length of each image, in order");
+	println!("\t--seed <seed>\t\tencode each byte as a random one of its offsets, chosen reproducibly from the seed");
+	println!("\t--rng <chacha20|pcg>\tthe generator used with --seed (default chacha20)");
+	println!("\t--limit-occurrences <k|none>\twith --seed, pick from at most k occurrences of each byte value (default 4096)");
+	println!("\t--min-occurrences <k>\trefuse to encode payload byte values that occur fewer than k times in the image");
+	println!("\t--block-size <n>\twith --seed, pick one offset per byte value for every n payload bytes");
+	println!("\t--chunk-size <n>\twith --seed or --checkpoint, cut the payload at content-defined boundaries about n bytes apart");
+	println!("\t--sliding-window <n>\tstore offsets relative to an n-byte window that moves through the image");
+	println!("\t--window-advance <n>\thow many bytes the sliding window moves for each payload byte (default 1)");
+	println!("\t--monotonic\t\tencode each byte as its first occurrence after the previous offset, so offsets strictly increase");
+	println!("\t--image-transform <grayscale|downsample>\tchoose offsets into the image as it will be after the transform");
+	println!("\t--strict-offsets\trecord the length of the image for strict decoding");
+	println!("\t--mac-key <key>\t\tauthenticate the container with an HMAC under the key");
+	println!("\t--avoid-bytes <list>\tonly use offsets that serialize without these hex bytes, e.g. 00,0a,0d");
+	println!("\t--content-type <type>\trecord a MIME type for the payload, or 'auto' to guess it from the payload");
+	println!("\t--regions <list>\trestrict byte values to image regions, e.g. 00-7f:0-4096,80-ff:4096-8192");
+	println!("\t--regions-file <path>\tread regions from a file, one per line");
+	println!("\t--escape\t\tstore bytes missing from the image as literals instead of failing");
+	println!("\t--payload-offset <n>\tonly encode the payload from this offset onwards");
+	println!("\t--payload-length <n>\tonly encode this many bytes of the payload");
+	println!("\t--compress-payload\tdeflate the payload before encoding it");
+	println!("\t--compress\t\tdeflate the encoded offsets");
+	println!("\t--verbose\t\tprint the payload and container sizes and the offsets used (also for decode)");
+	println!("\t--report-missing\tlist every payload byte value the image can't provide before encoding");
+	println!("\t--optimize\t\ttry every width, format and compression setting and keep the smallest container");
+	println!("\t--check-image\t\tfail before writing anything unless the image can encode every byte value");
+	println!("\t--checkpoint\t\tencode as a stream, recording progress in <output path>.checkpoint");
+	println!("\t--resume\t\tcarry on with an interrupted --checkpoint encode");
+	println!("\t--teach <path>\t\twrite a human-readable oracle mapping to the path (reveals the oracle!)");
+	println!("\t--bundle\t\twrite the image and the container together in one file (exposes the image!)");
+	println!("\t--archive <tar|zip>\twrite the container in a tar or zip file along with a metadata.json (default tar)");
+	println!("\t--audit <path>\t\twrite a JSON manifest of the payload, image and container hashes to the path");
+	println!("\t--audit-key <path>\tsign the manifest with the Ed25519 secret key in the file (64 hex digits)");
+	println!("\t--deterministic\t\treject options that could make the container differ between runs or versions");
+	println!("\t--time\t\t\tprint how long each phase took to stderr (also for decode)");
+	println!("\t--profile <path>\twrite a Chrome trace of each phase to the path (needs the profile feature; also for decode)");
+	println!("\t--max-image-bytes <n>\trefuse to read an image larger than n bytes (for every command)");
+	println!("\t--max-payload-bytes <n>\trefuse to read a payload larger than n bytes");
+	println!();
+	println!("DECODE/COMPARE/STATS OPTIONS:");
+	println!("\t--permute <key>\t\tthe key used to scramble the offsets, if any");
+	println!("\t--expect-width <n>\treject containers that don't declare this offset width");
+	println!("\t--strict-offsets\t(decode) check the image has the length recorded with --strict-offsets on encode");
+	println!("\t--mac-key <key>\t\tthe key the container was authenticated with; containers without a MAC are rejected");
+	println!("\t--auto-ext\t\t(decode) add an extension for the payload's type to an output path without one");
+	println!("\t--entry <name>\t\t(decode) only extract this entry from an archive");
+	println!("\t--archive\t\t(decode) take the container out of a tar or zip file written by encode --archive");
+	println!("\t--count-only\t\t(decode) print the payload length instead of writing it, with no output path");
+	println!();
+	println!("VERIFY-AUDIT OPTIONS:");
+	println!("\t--audit-public-key <path>\trequire the manifest to be signed by the Ed25519 public key in the file");
+	println!("\t--bundle\t\tthe encoded payload is a bundle");
+	println!("\t--archive\t\tthe encoded payload is a tar or zip file");
+	println!();
+	println!("FIND-IMAGE OPTIONS:");
+	println!("\t--all\t\t\treport every image that decodes the payload, not just the first");
+	println!();
+	println!("ANALYZE OPTIONS:");
+	println!("\t--histogram\t\tprint how many times each byte value occurs");
 }
 
 fn main() {
 	let args : Vec<String> = env::args().collect();
 	
-	if args.len() < 5 { return usage(); }
+	if args.len() < 2 { return usage(); }
 	
-	let input_path = args[2].to_string();
-	let output_path = args[3].to_string();
-	let image_path = args[4].to_string();
+	// Positional arguments come first, followed by any options.
+	let positional_count = args[2..].iter().take_while(|arg| !arg.starts_with("--")).count();
+	let positional = &args[2..2 + positional_count];
+	let mut options = match parse_options(&args[2 + positional_count..]) {
+		Ok(x) => x,
+		Err(e) => {
+			println!("{}", e);
+			return usage();
+		}
+	};
+	
+	// The guard has to outlive the command's span, so the trace is only written once the command has finished.
+	let _profile = options.profile.as_deref().map(start_profile);
+	#[cfg(feature = "profile")]
+	let _span = tracing::info_span!("command", name = %args[1]).entered();
 	
-	match args[1].as_str() {
-		"encode" => {
-			match encode_file(&input_path, &output_path, &image_path) {
+	match (args[1].as_str(), positional) {
+		("encode", [input_path, output_path, image_path]) => {
+			options.encode.archive = PathBuf::from(input_path).is_dir();
+			match encode_file(input_path, output_path, image_path, &options) {
 				Ok(_) => println!("Successfully encoded '{}' with '{}', result stored in '{}'", input_path, image_path, output_path),
 				Err(e) => println!("Failed to encode '{}' with '{}': {}", input_path, image_path, e)
 			}
 		},
-		"decode" => {
-			match decode_file(&input_path, &output_path, &image_path) {
-				Ok(_) => println!("Successfully decoded '{}' with '{}', result stored in '{}'", input_path, image_path, output_path),
+		("decode", [input_path, output_path, image_path]) => {
+			match decode_file(input_path, output_path, Some(image_path), &options) {
+				Ok(written_path) => println!("Successfully decoded '{}' with '{}', result stored in '{}'", input_path, image_path, written_path),
+				Err(e) => println!("Failed to decode '{}' with '{}': {}", input_path, image_path, e)
+			}
+		},
+		("decode", [input_path, output_path]) if options.bundle => {
+			match decode_file(input_path, output_path, None, &options) {
+				Ok(written_path) => println!("Successfully decoded bundle '{}', result stored in '{}'", input_path, written_path),
+				Err(e) => println!("Failed to decode bundle '{}': {}", input_path, e)
+			}
+		},
+		("decode", [input_path, image_path]) if options.count_only => {
+			match count_file(input_path, image_path, &options) {
+				Ok(length) => println!("'{}' decodes to {} bytes with '{}'", input_path, length, image_path),
 				Err(e) => println!("Failed to decode '{}' with '{}': {}", input_path, image_path, e)
 			}
 		},
-		_ => return usage()
+		("compare", [path_a, path_b, image_path]) => {
+			if let Err(e) = compare_files(path_a, path_b, image_path, &options) {
+				println!("Failed to compare '{}' and '{}': {}", path_a, path_b, e)
+			}
+		},
+		("analyze", [image_path]) => {
+			if let Err(e) = analyze_file(image_path, &options) {
+				println!("Failed to analyze '{}': {}", image_path, e)
+			}
+		},
+		("stats", [container_path, image_path]) => {
+			if let Err(e) = stats_file(container_path, image_path, &options) {
+				println!("Failed to get stats for '{}' with '{}': {}", container_path, image_path, e)
+			}
+		},
+		("bench-image", [payload_path, image_path]) => {
+			match bench_image(payload_path, image_path, &options) {
+				Ok(true) => (),
+				Ok(false) => process::exit(1),
+				Err(e) => {
+					println!("Failed to benchmark '{}' with '{}': {}", image_path, payload_path, e);
+					process::exit(1);
+				}
+			}
+		},
+		("verify-audit", [manifest_path, payload_path, image_path, container_path]) => {
+			options.encode.archive = PathBuf::from(payload_path).is_dir();
+			match verify_audit(manifest_path, payload_path, image_path, container_path, &options) {
+				Ok(Some(signer)) => println!("'{}' matches the files, and is signed by {}", manifest_path, hex(&signer)),
+				Ok(None) => println!("'{}' matches the files, but isn't signed", manifest_path),
+				Err(e) => println!("Failed to verify '{}': {}", manifest_path, e)
+			}
+		},
+		("repair", [input_path, output_path]) => {
+			match repair_file(input_path, output_path) {
+				Ok(dropped) => println!("Dropped {} trailing bytes from '{}', result stored in '{}'", dropped, input_path, output_path),
+				Err(e) => println!("Failed to repair '{}': {}", input_path, e)
+			}
+		},
+		("transcode-image", [container_path, old_image_path, new_image_path, output_path]) => {
+			match transcode_file(container_path, old_image_path, new_image_path, output_path, &options) {
+				Ok(_) => println!("Successfully transcoded '{}' from '{}' to '{}', result stored in '{}'", container_path, old_image_path, new_image_path, output_path),
+				Err(e) => println!("Failed to transcode '{}' from '{}' to '{}': {}", container_path, old_image_path, new_image_path, e)
+			}
+		},
+		("find-image", [container_path, dir_path]) => {
+			if let Err(e) = find_image(container_path, dir_path, &options) {
+				println!("Failed to search '{}' for the image of '{}': {}", dir_path, container_path, e)
+			}
+		},
+		_ => usage()
 	};
 }
@@ -0,0 +1,271 @@
+// Building oracles from an image, and using them to translate between payload bytes and image offsets.
+// Nothing here needs std, so it is available without the std feature.
+// Oracles are BTreeMaps rather than HashMaps, so iterating over one (to print or serialize it) always visits byte values in ascending order.
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::ops::Deref;
+
+// A sequence of image offsets produced by encoding a payload, kept apart from other vectors of integers.
+// Its serialization lives in the codec module, so that it stays next to the formats it is serialized in.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Offsets(Vec<u32>);
+
+impl Offsets {
+	pub fn new() -> Offsets {
+		Offsets(Vec::new())
+	}
+
+	// Check every offset indexes into an image of the given length.
+	// If one doesn't, it will return an error with the first offset that failed.
+	pub fn validate(&self, image_len: usize) -> Result<(),u32> {
+		match self.0.iter().find(|offset| **offset as usize >= image_len) {
+			Some(x) => Err(*x),
+			None => Ok(())
+		}
+	}
+
+	pub fn push(&mut self, offset: u32) {
+		self.0.push(offset);
+	}
+
+	pub fn into_vec(self) -> Vec<u32> {
+		self.0
+	}
+}
+
+impl Deref for Offsets {
+	type Target = [u32];
+
+	fn deref(&self) -> &[u32] {
+		&self.0
+	}
+}
+
+impl From<Vec<u32>> for Offsets {
+	fn from(offsets: Vec<u32>) -> Offsets {
+		Offsets(offsets)
+	}
+}
+
+impl From<Offsets> for Vec<u32> {
+	fn from(offsets: Offsets) -> Vec<u32> {
+		offsets.0
+	}
+}
+
+impl FromIterator<u32> for Offsets {
+	fn from_iter<I: IntoIterator<Item = u32>>(iter: I) -> Offsets {
+		Offsets(iter.into_iter().collect())
+	}
+}
+
+impl Extend<u32> for Offsets {
+	fn extend<I: IntoIterator<Item = u32>>(&mut self, iter: I) {
+		self.0.extend(iter);
+	}
+}
+
+impl<'a> IntoIterator for &'a Offsets {
+	type Item = &'a u32;
+	type IntoIter = core::slice::Iter<'a, u32>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.0.iter()
+	}
+}
+
+// Create an metasteganographic oracle from an array of bytes.
+// If it fails to find a corresponding value for a byte, it will return an error with the byte that failed.
+// Values are tried in ascending order, so an image without every value (down to a single byte, or none) fails on the lowest one it lacks.
+pub fn create_oracle(buf : &[u8]) -> Result<BTreeMap<u8, u32>,u8> {
+	let mut oracle : BTreeMap<u8, u32> = BTreeMap::new();
+	
+	for i in 0..256 {
+		let byte = i as u8;
+		let buflen : u32 = buf.len() as u32;
+		
+		for offset in 0..buflen {
+			let current_value = buf[offset as usize];
+			if current_value == byte {
+				oracle.insert(byte, offset);
+				break;
+			}
+		}
+		if !oracle.contains_key(&byte) {
+			return Err(byte);
+		}
+	}
+	
+	Ok(oracle)
+}
+
+// Create an oracle that records every offset at which each byte value occurs, in ascending order.
+// Unlike create_oracle, this never fails; byte values that don't occur in the buffer are simply absent.
+pub fn create_oracle_all(buf : &[u8]) -> BTreeMap<u8, Vec<u32>> {
+	let mut oracle : BTreeMap<u8, Vec<u32>> = BTreeMap::new();
+	for (offset, byte) in buf.iter().enumerate() {
+		oracle.entry(*byte).or_default().push(offset as u32);
+	}
+	oracle
+}
+
+// Like create_oracle_all, but only records the offsets the predicate allows, and at most limit of them for each byte value.
+// When a value has more allowed offsets than that, the ones kept are a uniform random sample (by reservoir sampling),
+// where below(n) draws a random number below n. The kept offsets are in ascending order.
+pub fn create_oracle_sampled(buf : &[u8], allowed: impl Fn(u8, u32) -> bool, limit: Option<usize>, mut below: impl FnMut(u64) -> u64) -> BTreeMap<u8, Vec<u32>> {
+	let mut oracle : BTreeMap<u8, Vec<u32>> = BTreeMap::new();
+	let mut seen = [0u64;256];
+	for (offset, byte) in buf.iter().enumerate() {
+		let offset = offset as u32;
+		if !allowed(*byte, offset) {
+			continue;
+		}
+		seen[*byte as usize] += 1;
+		let kept = oracle.entry(*byte).or_default();
+		match limit {
+			// The nth allowed offset replaces a kept one with probability limit/n.
+			Some(limit) if kept.len() >= limit => {
+				let slot = below(seen[*byte as usize]) as usize;
+				if slot < limit {
+					kept[slot] = offset;
+				}
+			},
+			_ => kept.push(offset)
+		}
+	}
+	for offsets in oracle.values_mut() {
+		offsets.sort_unstable();
+	}
+	oracle.retain(|_, offsets| !offsets.is_empty());
+	oracle
+}
+
+// Count how many times each byte value occurs in a buffer.
+pub fn count_occurrences(buf : &[u8]) -> [u64;256] {
+	let mut counts = [0u64;256];
+	for byte in buf {
+		counts[*byte as usize] += 1;
+	}
+	counts
+}
+
+// Pick an offset for each byte value from an all-occurrences oracle, only considering offsets below max_offset.
+// If a byte has no occurrence inside the window, it will return an error with the byte that failed.
+pub fn create_oracle_window(oracle_all: &BTreeMap<u8, Vec<u32>>, max_offset: u32) -> Result<BTreeMap<u8, u32>,u8> {
+	let mut oracle : BTreeMap<u8, u32> = BTreeMap::new();
+	for i in 0..256 {
+		let byte = i as u8;
+		let offset = match oracle_all.get(&byte) {
+			Some(offsets) => offsets.iter().find(|offset| **offset < max_offset),
+			None => None
+		};
+		match offset {
+			Some(x) => oracle.insert(byte, *x),
+			None => return Err(byte)
+		};
+	}
+	Ok(oracle)
+}
+
+// Pick the first offset of every byte value that occurs in an all-occurrences oracle, optionally only below max_offset.
+// Unlike create_oracle, byte values that don't occur (in the window) are left out instead of causing an error.
+pub fn create_oracle_partial(oracle_all: &BTreeMap<u8, Vec<u32>>, max_offset: Option<u32>) -> BTreeMap<u8, u32> {
+	let mut oracle : BTreeMap<u8, u32> = BTreeMap::new();
+	for (byte, offsets) in oracle_all {
+		let offset = match max_offset {
+			Some(max_offset) => offsets.iter().find(|offset| **offset < max_offset),
+			None => offsets.first()
+		};
+		if let Some(x) = offset {
+			oracle.insert(*byte, *x);
+		}
+	}
+	oracle
+}
+
+// Pick the first offset of every byte value in an all-occurrences oracle that the predicate allows for that value.
+// As with create_oracle_partial, byte values with no allowed offset are left out.
+pub fn create_oracle_filtered(oracle_all: &BTreeMap<u8, Vec<u32>>, allowed: impl Fn(u8, u32) -> bool) -> BTreeMap<u8, u32> {
+	let mut oracle : BTreeMap<u8, u32> = BTreeMap::new();
+	for (byte, offsets) in oracle_all {
+		if let Some(x) = offsets.iter().find(|offset| allowed(*byte, **offset)) {
+			oracle.insert(*byte, *x);
+		}
+	}
+	oracle
+}
+
+// A byte value the payload uses but an oracle can't translate, and where it occurs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingByte {
+	pub byte: u8,
+	// The number of payload positions holding the byte.
+	pub count: usize,
+	// The first of those positions.
+	pub first: usize
+}
+
+// Find every byte value in a payload that an oracle can't translate, in ascending order of value.
+pub fn find_missing(payload: &[u8], oracle: &BTreeMap<u8, u32>) -> Vec<MissingByte> {
+	let mut missing : BTreeMap<u8, MissingByte> = BTreeMap::new();
+	for (position, byte) in payload.iter().enumerate() {
+		if !oracle.contains_key(byte) {
+			missing.entry(*byte).or_insert(MissingByte { byte: *byte, count: 0, first: position }).count += 1;
+		}
+	}
+	missing.into_values().collect()
+}
+
+// A byte value the payload uses that occurs fewer times in the image than required.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RareByte {
+	pub byte: u8,
+	// The number of times it occurs in the image.
+	pub occurrences: u64
+}
+
+// Find every byte value in a payload that occurs fewer than minimum times, given how many times each value occurs, in ascending order of value.
+pub fn find_rare(payload: &[u8], occurrences: &[u64;256], minimum: u64) -> Vec<RareByte> {
+	let mut used = [false;256];
+	for byte in payload {
+		used[*byte as usize] = true;
+	}
+	(0..256).filter(|value| used[*value] && occurrences[*value] < minimum).map(|value| RareByte { byte: value as u8, occurrences: occurrences[value] }).collect()
+}
+
+// Use an oracle to encode a payload metasteganographically.
+// If it fails to translate a byte from the payload, it will return an error with the byte that failed.
+pub fn metasteg_encode(payload: &[u8], oracle: &BTreeMap<u8, u32>) -> Result<Offsets,u8> {
+	let mut encoded = Offsets::new();
+	for byte in payload {
+		let encoded_offset = match oracle.get(byte) {
+			Some(b) => *b,
+			None => return Err(*byte)
+		};
+		encoded.push(encoded_offset);
+	}
+	Ok(encoded)
+}
+
+// Use an oracle to encode a payload, escaping bytes the oracle can't translate.
+// Each missing byte is written as the sentinel followed by the byte's literal value.
+pub fn metasteg_encode_escaped(payload: &[u8], oracle: &BTreeMap<u8, u32>, sentinel: u32) -> Offsets {
+	let mut encoded = Offsets::new();
+	for byte in payload {
+		match oracle.get(byte) {
+			Some(b) => encoded.push(*b),
+			None => {
+				encoded.push(sentinel);
+				encoded.push(*byte as u32);
+			}
+		}
+	}
+	encoded
+}
+
+// Use the original buffer to decode a payload metasteganographically.
+// If it fails to translate an offset from the payload, it will return an error with the offset that failed.
+pub fn metasteg_decode(payload: &Offsets, buf: &[u8]) -> Result<Vec<u8>,u32> {
+	payload.validate(buf.len())?;
+	Ok(payload.iter().map(|offset| buf[*offset as usize]).collect())
+}
@@ -0,0 +1,150 @@
+// Packages: a tar or zip file holding a container and a metadata.json describing it, for archiving the two together.
+// Unlike a bundle, a package never holds the image, only its hash. The metadata is written as JSON so it can be read without metastego.
+use std::io::{Cursor, Read, Write};
+
+use serde_json::{json, Value};
+
+use crate::{MetastegError, sha256};
+use crate::audit::{hex, parse_hex};
+use crate::codec::Format;
+use crate::header::parse_header;
+
+// The names of the two entries in a package.
+pub const CONTAINER_ENTRY : &str = "container.mstg";
+pub const METADATA_ENTRY : &str = "metadata.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackageFormat {
+	Tar,
+	Zip
+}
+
+impl PackageFormat {
+	pub fn from_name(name: &str) -> Option<PackageFormat> {
+		match name {
+			"tar" => Some(PackageFormat::Tar),
+			"zip" => Some(PackageFormat::Zip),
+			_ => None
+		}
+	}
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			PackageFormat::Tar => "tar",
+			PackageFormat::Zip => "zip"
+		}
+	}
+
+	// Work out which format a package is in from its leading bytes. Tar files have no magic at the start, so anything that isn't a zip is tried as a tar.
+	pub fn detect(package: &[u8]) -> PackageFormat {
+		match package.starts_with(b"PK\x03\x04") {
+			true => PackageFormat::Zip,
+			false => PackageFormat::Tar
+		}
+	}
+}
+
+// Describe a container and the image it was encoded with: the image's hash and length, the container's settings and the tool version.
+pub fn metadata(container: &[u8], image: &[u8]) -> Result<String,MetastegError> {
+	let (header, _) = parse_header(container)?;
+	let value = json!({
+		"tool_version": env!("CARGO_PKG_VERSION"),
+		"image_sha256": hex(&sha256(image)),
+		"image_length": image.len(),
+		"format": Format::from_flags(header.flags).name(),
+		"width": header.width,
+		"flags": header.flags
+	});
+	let mut json = serde_json::to_string_pretty(&value).unwrap();
+	json.push('\n');
+	Ok(json)
+}
+
+// The hash of the image recorded in a package's metadata, if it has one.
+pub fn metadata_image_hash(metadata: &str) -> Result<Option<[u8;32]>,MetastegError> {
+	let value : Value = serde_json::from_str(metadata).map_err(|e| MetastegError::InvalidPackage(format!("{} isn't valid JSON: {}", METADATA_ENTRY, e)))?;
+	match value.get("image_sha256") {
+		None => Ok(None),
+		Some(Value::String(digest)) => match parse_hex::<32>(digest) {
+			Some(x) => Ok(Some(x)),
+			None => Err(MetastegError::InvalidPackage(format!("the image hash in {} is malformed", METADATA_ENTRY)))
+		},
+		Some(_) => Err(MetastegError::InvalidPackage(format!("the image hash in {} isn't a string", METADATA_ENTRY)))
+	}
+}
+
+// Package a container with its metadata. Entries are written in a fixed order with fixed timestamps, so the same container always gives the same package.
+pub fn package(format: PackageFormat, container: &[u8], metadata: &str) -> Result<Vec<u8>,MetastegError> {
+	let entries = [(METADATA_ENTRY, metadata.as_bytes()), (CONTAINER_ENTRY, container)];
+	match format {
+		PackageFormat::Tar => {
+			let mut builder = tar::Builder::new(Vec::new());
+			for (name, data) in entries {
+				let mut header = tar::Header::new_ustar();
+				header.set_size(data.len() as u64);
+				header.set_mode(0o644);
+				header.set_mtime(0);
+				builder.append_data(&mut header, name, data)?;
+			}
+			Ok(builder.into_inner()?)
+		},
+		PackageFormat::Zip => {
+			let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+			// The container is usually already as small as it gets, so the entries are stored rather than deflated.
+			let options = zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+			for (name, data) in entries {
+				writer.start_file(name, options).map_err(|e| MetastegError::InvalidPackage(e.to_string()))?;
+				writer.write_all(data)?;
+			}
+			Ok(writer.finish().map_err(|e| MetastegError::InvalidPackage(e.to_string()))?.into_inner())
+		}
+	}
+}
+
+// Take the container and metadata out of a package, in either format.
+// The container entry has to be there, but the metadata is optional.
+pub fn unpackage(package: &[u8]) -> Result<(Vec<u8>, Option<String>),MetastegError> {
+	let mut container = None;
+	let mut metadata = None;
+	match PackageFormat::detect(package) {
+		PackageFormat::Tar => {
+			let mut archive = tar::Archive::new(package);
+			let entries = archive.entries().map_err(|e| MetastegError::InvalidPackage(e.to_string()))?;
+			for entry in entries {
+				let mut entry = entry.map_err(|e| MetastegError::InvalidPackage(e.to_string()))?;
+				let name = entry.path().map_err(|e| MetastegError::InvalidPackage(e.to_string()))?.to_string_lossy().to_string();
+				let slot = match name.as_str() {
+					CONTAINER_ENTRY => &mut container,
+					METADATA_ENTRY => &mut metadata,
+					_ => continue
+				};
+				let mut data = Vec::new();
+				entry.read_to_end(&mut data).map_err(|e| MetastegError::InvalidPackage(e.to_string()))?;
+				*slot = Some(data);
+			}
+		},
+		PackageFormat::Zip => {
+			let mut archive = zip::ZipArchive::new(Cursor::new(package)).map_err(|e| MetastegError::InvalidPackage(e.to_string()))?;
+			for (name, slot) in [(CONTAINER_ENTRY, &mut container), (METADATA_ENTRY, &mut metadata)] {
+				let mut entry = match archive.by_name(name) {
+					Ok(x) => x,
+					Err(zip::result::ZipError::FileNotFound) => continue,
+					Err(e) => return Err(MetastegError::InvalidPackage(e.to_string()))
+				};
+				let mut data = Vec::new();
+				entry.read_to_end(&mut data).map_err(|e| MetastegError::InvalidPackage(e.to_string()))?;
+				*slot = Some(data);
+			}
+		}
+	}
+	let container = match container {
+		Some(x) => x,
+		None => return Err(MetastegError::InvalidPackage(format!("there is no {} entry", CONTAINER_ENTRY)))
+	};
+	let metadata = match metadata.map(String::from_utf8) {
+		Some(Ok(x)) => Some(x),
+		Some(Err(_)) => return Err(MetastegError::InvalidPackage(format!("{} isn't valid UTF-8", METADATA_ENTRY))),
+		None => None
+	};
+	Ok((container, metadata))
+}
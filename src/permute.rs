@@ -0,0 +1,30 @@
+// Keyed permutation of the offset sequence, so the stored order doesn't match the payload order.
+use crate::rng::{RngAlgorithm, SeededRng};
+
+// Produce the permutation of 0..length for a key with a seeded Fisher-Yates shuffle.
+// The permutation always uses ChaCha20, since decoding has to reproduce it and nothing records which generator was used.
+// It has its own label, so a key that is also used as a seed doesn't give the same stream.
+// Position i of the permuted sequence holds the element at position permutation[i] of the original.
+pub fn permutation(key: &str, length: usize) -> Vec<usize> {
+	let mut rng = SeededRng::derived(RngAlgorithm::ChaCha20, key, b"permutation");
+	let mut permutation : Vec<usize> = (0..length).collect();
+	for i in (1..length).rev() {
+		let j = rng.below(i as u64 + 1) as usize;
+		permutation.swap(i, j);
+	}
+	permutation
+}
+
+// Scramble the order of a sequence of offsets with a key.
+pub fn permute(offsets: &[u32], key: &str) -> Vec<u32> {
+	permutation(key, offsets.len()).iter().map(|i| offsets[*i]).collect()
+}
+
+// Restore the original order of a sequence of offsets scrambled by permute with the same key.
+pub fn unpermute(offsets: &[u32], key: &str) -> Vec<u32> {
+	let mut original : Vec<u32> = vec![0; offsets.len()];
+	for (position, i) in permutation(key, offsets.len()).iter().enumerate() {
+		original[*i] = offsets[position];
+	}
+	original
+}
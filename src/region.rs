@@ -0,0 +1,41 @@
+// Constraints on which part of the image each byte value may be encoded from.
+use std::ops::{Range, RangeInclusive};
+
+// Byte values in a range may only be encoded with offsets in another range.
+// Byte values not covered by any region can use any offset; a value covered by several regions can use any of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+	pub values: RangeInclusive<u8>,
+	pub offsets: Range<u32>
+}
+
+impl Region {
+	// Parse a region written as "<values>:<offsets>", where the values are a hex byte or an inclusive range of them
+	// and the offsets are a decimal range with an exclusive end, e.g. "00-7f:0-4096" or "0a:100-200".
+	pub fn parse(spec: &str) -> Option<Region> {
+		let (values, offsets) = spec.trim().split_once(':')?;
+		let values = match values.split_once('-') {
+			Some((start, end)) => u8::from_str_radix(start, 16).ok()?..=u8::from_str_radix(end, 16).ok()?,
+			None => {
+				let value = u8::from_str_radix(values, 16).ok()?;
+				value..=value
+			}
+		};
+		let (start, end) = offsets.split_once('-')?;
+		let offsets = start.parse::<u32>().ok()?..end.parse::<u32>().ok()?;
+		if values.is_empty() || offsets.is_empty() {
+			return None;
+		}
+		Some(Region { values, offsets })
+	}
+}
+
+// Whether any region covers a byte value.
+pub fn is_constrained(regions: &[Region], value: u8) -> bool {
+	regions.iter().any(|region| region.values.contains(&value))
+}
+
+// Whether the regions let a byte value be encoded with an offset.
+pub fn region_allows(regions: &[Region], value: u8, offset: u32) -> bool {
+	!is_constrained(regions, value) || regions.iter().any(|region| region.values.contains(&value) && region.offsets.contains(&offset))
+}
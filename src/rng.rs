@@ -0,0 +1,87 @@
+// Seeded random number generators, for the parts of encoding that make random choices.
+// The algorithms are portable and fixed, so the same seed produces the same choices on any host and with any version of this crate.
+use rand_chacha::ChaCha20Rng;
+use rand_chacha::rand_core::{Rng, SeedableRng};
+use rand_pcg::Pcg64;
+use sha2::{Digest, Sha256};
+
+// The algorithm a seeded generator uses. Changing it changes what a given seed produces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RngAlgorithm {
+	#[default]
+	ChaCha20,
+	Pcg
+}
+
+impl RngAlgorithm {
+	pub fn from_name(name: &str) -> Option<RngAlgorithm> {
+		match name {
+			"chacha20" => Some(RngAlgorithm::ChaCha20),
+			"pcg" => Some(RngAlgorithm::Pcg),
+			_ => None
+		}
+	}
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			RngAlgorithm::ChaCha20 => "chacha20",
+			RngAlgorithm::Pcg => "pcg"
+		}
+	}
+}
+
+pub enum SeededRng {
+	ChaCha20(Box<ChaCha20Rng>),
+	Pcg(Pcg64)
+}
+
+impl SeededRng {
+	// Create a generator from a seed string. The generator's seed is derived from it, so it can be any length.
+	pub fn new(algorithm: RngAlgorithm, seed: &str) -> SeededRng {
+		SeededRng::from_seed(algorithm, Sha256::digest(seed.as_bytes()).into())
+	}
+
+	// Create a generator for one of a sequence of blocks, seeded from both the seed string and the block's index.
+	pub fn for_block(algorithm: RngAlgorithm, seed: &str, index: u64) -> SeededRng {
+		SeededRng::derived(algorithm, seed, &index.to_be_bytes())
+	}
+
+	// Create a generator for a content-defined chunk, seeded from both the seed string and the chunk's bytes.
+	// Unlike for_block, a chunk gets the same generator wherever it ends up in the payload.
+	pub fn for_chunk(algorithm: RngAlgorithm, seed: &str, chunk: &[u8]) -> SeededRng {
+		SeededRng::derived(algorithm, seed, &[b"chunk".as_slice(), chunk].concat())
+	}
+
+	// Create a generator seeded from a seed string and a label, so one seed can drive several independent sequences of choices.
+	// The seed's length goes first, so no two seed and label pairs hash the same bytes.
+	pub fn derived(algorithm: RngAlgorithm, seed: &str, label: &[u8]) -> SeededRng {
+		let mut hasher = Sha256::new();
+		hasher.update((seed.len() as u64).to_be_bytes());
+		hasher.update(seed.as_bytes());
+		hasher.update(label);
+		SeededRng::from_seed(algorithm, hasher.finalize().into())
+	}
+
+	fn from_seed(algorithm: RngAlgorithm, seed: [u8;32]) -> SeededRng {
+		match algorithm {
+			RngAlgorithm::ChaCha20 => SeededRng::ChaCha20(Box::new(ChaCha20Rng::from_seed(seed))),
+			RngAlgorithm::Pcg => SeededRng::Pcg(Pcg64::from_seed(seed))
+		}
+	}
+
+	pub fn next_u64(&mut self) -> u64 {
+		match self {
+			SeededRng::ChaCha20(rng) => rng.next_u64(),
+			SeededRng::Pcg(rng) => rng.next_u64()
+		}
+	}
+
+	// Pick a uniformly distributed value below n, rejecting draws that would bias the result.
+	pub fn below(&mut self, n: u64) -> u64 {
+		let zone = u64::MAX - u64::MAX % n;
+		loop {
+			let x = self.next_u64();
+			if x < zone { return x % n; }
+		}
+	}
+}
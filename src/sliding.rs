@@ -0,0 +1,37 @@
+// Sliding windows: offsets stored relative to a window that moves through the image as the payload is encoded.
+// However large the image, every stored offset is smaller than the window, so a narrow width is enough to hold it.
+use crate::MetastegError;
+use crate::oracle::Offsets;
+
+// A window of size bytes, whose base moves forward by advance bytes for every payload byte.
+// The base wraps around to the start of the image before the window would run off its end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlidingWindow {
+	pub size: u32,
+	pub advance: u32
+}
+
+impl SlidingWindow {
+	// The image offset the window starts at for the payload byte at a position.
+	pub fn base(&self, position: usize, image_len: usize) -> Result<u32,MetastegError> {
+		if self.size == 0 || self.size as usize > image_len {
+			return Err(MetastegError::WindowLargerThanImage { size: self.size, image_len: image_len as u64 });
+		}
+		// The number of places the window can start at. u128 so the product can't overflow.
+		let span = (image_len - self.size as usize + 1) as u128;
+		Ok((position as u128 * self.advance as u128 % span) as u32)
+	}
+
+	// Turn offsets relative to the window back into image offsets, for the payload bytes from position start onwards.
+	// An offset that doesn't fit inside the window is an error, since the encoder could never have produced it.
+	pub fn to_absolute(&self, offsets: &Offsets, start: usize, image_len: usize) -> Result<Offsets,MetastegError> {
+		let mut absolute = Offsets::new();
+		for (i, offset) in offsets.iter().enumerate() {
+			if *offset >= self.size {
+				return Err(MetastegError::OffsetOutsideWindow { offset: *offset, max_offset: self.size });
+			}
+			absolute.push(self.base(start + i, image_len)? + offset);
+		}
+		Ok(absolute)
+	}
+}
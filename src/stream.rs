@@ -0,0 +1,652 @@
+// Streaming encoding and decoding, for payloads and containers that arrive in chunks.
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
+
+use sha2::{Digest, Sha256};
+
+use crate::{MetastegError, EncodeOptions, EncodeReport, DecodeOptions, image_offsets, sha256, sniff_content_type, check_width, check_declared_width, check_fingerprint, check_images, check_image_length, check_length, check_escape_complete, sentinel_for_width, decode_offsets, container_mac};
+use crate::chunk;
+use crate::codec::{OffsetCodec, Codec, Format, codec_for};
+use crate::compress::deflate;
+use crate::header::{Header, FLAG_PERMUTED, FLAG_COMPRESSED_OFFSETS, FLAG_COMPRESSED_PAYLOAD, FLAG_ARCHIVE, serialize_header, parse_header_partial};
+use crate::oracle::{create_oracle, create_oracle_all, create_oracle_window, create_oracle_partial, create_oracle_filtered, create_oracle_sampled, find_rare, metasteg_encode, metasteg_encode_escaped, Offsets};
+use crate::permute::permute;
+use crate::region::{is_constrained, region_allows};
+use crate::rng::SeededRng;
+use crate::sliding::SlidingWindow;
+use crate::transform::transform_image;
+
+// Encodes a payload chunk by chunk, producing the serialized container as it goes.
+// The header is emitted in front of the first chunk of offsets.
+// Permuted, checksummed, compressed or variable-length containers can't be produced as a stream, since they depend on the whole payload.
+pub struct Encoder {
+	oracle: BTreeMap<u8, u32>,
+	// Every offset each byte value may be encoded as, for picking between them at random when the options have a seed.
+	candidates: Option<BTreeMap<u8, Vec<u32>>>,
+	header: Header,
+	options: EncodeOptions,
+	// The length of the image, when the encoder was created from one.
+	image_len: Option<usize>,
+	// How many payload bytes have been encoded by update, which a sliding window moves with.
+	position: usize,
+	// How many allowed offsets each byte value has, when the options set a minimum.
+	occurrences: Option<[u64;256]>,
+	header_written: bool
+}
+
+// Build the oracle for an image, restricted to the window in the options if there is one.
+// With escapes enabled, the oracle only covers the byte values the image has and never fails.
+// If the options have an image transform, the oracle indexes into the transformed image.
+pub fn build_oracle(image: &[u8], options: &EncodeOptions) -> Result<BTreeMap<u8, u32>,MetastegError> {
+	let image = &transform_image(options.transform, image);
+	match build_oracle_untransformed(image, options) {
+		Err(MetastegError::MissingByteInImage { byte, .. }) if options.transform.is_some() => Err(MetastegError::MissingByteAfterTransform { byte, transform: options.transform.unwrap().name() }),
+		x => x
+	}
+}
+
+fn build_oracle_untransformed(image: &[u8], options: &EncodeOptions) -> Result<BTreeMap<u8, u32>,MetastegError> {
+	if !options.avoid_bytes.is_empty() || !options.regions.is_empty() {
+		return build_oracle_constrained(image, options);
+	}
+	if options.escape {
+		return Ok(create_oracle_partial(&create_oracle_all(image), options.max_offset));
+	}
+	match options.max_offset {
+		Some(max_offset) => match create_oracle_window(&create_oracle_all(image), max_offset) {
+			Ok(x) => Ok(x),
+			Err(e) => Err(MetastegError::MissingByteInImage { byte: e, max_offset: Some(max_offset) })
+		},
+		None => match create_oracle(image) {
+			Ok(x) => Ok(x),
+			Err(e) => Err(MetastegError::MissingByteInImage { byte: e, max_offset: None })
+		}
+	}
+}
+
+// Build the oracle the options would use, but leave out byte values the image can't provide instead of failing.
+// This is for finding out what a payload needs from an image before trying to encode it.
+pub fn build_partial_oracle(image: &[u8], options: &EncodeOptions) -> Result<BTreeMap<u8, u32>,MetastegError> {
+	let image = &transform_image(options.transform, image);
+	if !options.avoid_bytes.is_empty() || !options.regions.is_empty() {
+		return build_oracle_constrained(image, options);
+	}
+	Ok(create_oracle_partial(&create_oracle_all(image), options.max_offset))
+}
+
+// Build an oracle that only uses offsets allowed by the regions in the options, and whose serialization avoids the forbidden bytes.
+// Byte values without such an offset are left out, and only cause an error if the payload contains them.
+fn build_oracle_constrained(image: &[u8], options: &EncodeOptions) -> Result<BTreeMap<u8, u32>,MetastegError> {
+	// Compressing or escaping would put bytes in the container that weren't chosen to avoid anything.
+	if !options.avoid_bytes.is_empty() && (options.compress_offsets || options.escape) {
+		return Err(MetastegError::UnsupportedFeature("avoiding bytes can't be combined with compressed offsets or escapes".to_string()));
+	}
+	// Nor are dictionary indices chosen to avoid anything.
+	if !options.avoid_bytes.is_empty() && options.format == Format::Dictionary {
+		return Err(MetastegError::UnsupportedFeature("avoiding bytes can't be combined with dictionary offsets".to_string()));
+	}
+	let codec = codec_for_options(options);
+	Ok(create_oracle_filtered(&create_oracle_all(image), |value, offset| offset_allowed(options, &codec, value, offset)))
+}
+
+// Every offset of each byte value in the image that the options allow, for picking between them with a seed.
+// Byte values without an allowed offset are left out, as with build_partial_oracle.
+// If the options limit the occurrences kept for each value, a random sample of that many is kept instead, to bound the memory used.
+fn build_candidates(image: &[u8], options: &EncodeOptions) -> BTreeMap<u8, Vec<u32>> {
+	let codec = codec_for_options(options);
+	// Unlike the first occurrence, a random one could be too large for the offset width, so those are left out too.
+	// With escapes, the largest value is the sentinel and can't be used either.
+	let largest = sentinel_for_width(options.width);
+	let fits = |offset: u32| (options.format == Format::Varint || offset <= largest) && !(options.escape && offset == largest);
+	// Which offsets are kept when a value has more than the limit is itself decided by the seed.
+	let mut rng = SeededRng::derived(options.rng, options.seed.as_deref().unwrap_or_default(), b"occurrences");
+	create_oracle_sampled(&transform_image(options.transform, image), |value, offset| fits(offset) && offset_allowed(options, &codec, value, offset),
+		options.limit_occurrences, |n| rng.below(n))
+}
+
+// How many times each byte value occurs at an offset the options allow, in the image the offsets index into.
+fn count_allowed(image: &[u8], options: &EncodeOptions) -> [u64;256] {
+	let codec = codec_for_options(options);
+	let mut counts = [0u64;256];
+	for (value, offsets) in create_oracle_all(&transform_image(options.transform, image)) {
+		counts[value as usize] = offsets.iter().filter(|offset| offset_allowed(options, &codec, value, **offset)).count() as u64;
+	}
+	counts
+}
+
+// The codec the options would serialize offsets with.
+fn codec_for_options(options: &EncodeOptions) -> Codec {
+	let mut header = Header::new();
+	header.width = options.width;
+	header.flags = options.format.flag();
+	codec_for(&header)
+}
+
+// Whether the options allow a byte value to be encoded as an offset: inside the window and the value's regions,
+// and serialized without any of the forbidden bytes.
+fn offset_allowed(options: &EncodeOptions, codec: &Codec, value: u8, offset: u32) -> bool {
+	if options.max_offset.is_some_and(|max_offset| offset >= max_offset) || !region_allows(&options.regions, value, offset) {
+		return false;
+	}
+	if options.avoid_bytes.is_empty() {
+		return true;
+	}
+	match codec.serialize_to_vec(&[offset]) {
+		Ok(serialized) => !serialized.iter().any(|x| options.avoid_bytes.contains(x)),
+		Err(_) => false
+	}
+}
+
+// How much of the payload is hashed at a time while it is encoded: small enough to still be in cache when its bytes are translated.
+const HASHED_PIECE : usize = 64 * 1024;
+
+// Split a payload into pieces to encode one after another, feeding each to the hasher (if there is one) as it is reached.
+fn hashed_pieces<'a>(payload: &'a [u8], hasher: &'a mut Option<Sha256>) -> impl Iterator<Item = &'a [u8]> + 'a {
+	payload.chunks(HASHED_PIECE).inspect(move |piece| {
+		if let Some(hasher) = hasher.as_mut() {
+			hasher.update(piece);
+		}
+	})
+}
+
+// Check a container doesn't use any flags that need the whole container at once.
+fn check_streamable(flags: u32) -> Result<(),MetastegError> {
+	if flags & FLAG_PERMUTED != 0 {
+		return Err(MetastegError::UnsupportedFeature("permuted containers can't be streamed".to_string()));
+	}
+	if flags & (FLAG_COMPRESSED_OFFSETS | FLAG_COMPRESSED_PAYLOAD) != 0 {
+		return Err(MetastegError::UnsupportedFeature("compressed containers can't be streamed".to_string()));
+	}
+	if Format::from_flags(flags) != Format::Fixed {
+		return Err(MetastegError::UnsupportedFeature(format!("{} offsets can't be streamed", Format::from_flags(flags).name())));
+	}
+	Ok(())
+}
+
+// Resolve an optional offset and length into a range of a payload, checking it stays inside the payload.
+fn select_range(payload_len: usize, offset: Option<u64>, length: Option<u64>) -> Result<Range<usize>,MetastegError> {
+	let start = offset.unwrap_or(0);
+	let length = match length {
+		Some(x) => x,
+		None => (payload_len as u64).saturating_sub(start)
+	};
+	match start.checked_add(length) {
+		Some(end) if end <= payload_len as u64 => Ok(start as usize..end as usize),
+		_ => Err(MetastegError::InvalidPayloadRange { offset: start, length, payload_len })
+	}
+}
+
+impl Encoder {
+	// Build the oracle for an image and prepare to encode with it.
+	pub fn new(image: &[u8], options: &EncodeOptions) -> Result<Encoder,MetastegError> {
+		#[cfg(feature = "profile")]
+		let _span = tracing::info_span!("oracle").entered();
+		let mut encoder = Encoder::from_oracle(build_oracle(image, options)?, options)?;
+		if options.fingerprint {
+			encoder.header.fingerprint = Some(sha256(image));
+		}
+		if options.image_list {
+			encoder.header.images = Some(vec![(sha256(image), image.len() as u64)]);
+		}
+		if options.seed.is_some() {
+			encoder.candidates = Some(build_candidates(image, options));
+		}
+		if let Some(window) = options.sliding_window {
+			// Every occurrence is kept, so whichever part of the image the window is over, the first one inside it can be found.
+			let image = transform_image(options.transform, image);
+			window.base(0, image.len())?;
+			encoder.candidates = Some(create_oracle_all(&image));
+		}
+		if options.monotonic {
+			// Every occurrence is kept, so there is always the earliest one after any offset to pick.
+			encoder.candidates = Some(create_oracle_all(&transform_image(options.transform, image)));
+		}
+		if options.min_occurrences.is_some() {
+			encoder.occurrences = Some(count_allowed(image, options));
+		}
+		if options.record_image_length {
+			encoder.header.image_length = Some(image.len() as u64);
+		}
+		encoder.image_len = Some(image.len());
+		Ok(encoder)
+	}
+
+	// Prepare to encode with an oracle that has already been built with build_oracle, so it can be reused.
+	// Without the image there is nothing to fingerprint, measure or pick random offsets from, so the fingerprint, image list, image length and seed options are left to Encoder::new.
+	pub fn from_oracle(oracle: BTreeMap<u8, u32>, options: &EncodeOptions) -> Result<Encoder,MetastegError> {
+		check_width(options.width)?;
+		match options.block_size {
+			Some(_) if options.seed.is_none() => return Err(MetastegError::UnsupportedFeature("a block size needs a seed to pick each block's offsets with".to_string())),
+			Some(0) => return Err(MetastegError::UnsupportedFeature("the block size must be at least one byte".to_string())),
+			_ => ()
+		}
+		match options.chunk_average {
+			Some(_) if options.seed.is_none() => return Err(MetastegError::UnsupportedFeature("content-defined chunks need a seed to pick each chunk's offsets with".to_string())),
+			Some(_) if options.block_size.is_some() => return Err(MetastegError::UnsupportedFeature("blocks can be a fixed size or content-defined, but not both".to_string())),
+			Some(x) if x < chunk::MIN_AVERAGE => return Err(MetastegError::UnsupportedFeature(format!("the average chunk size must be at least {} bytes", chunk::MIN_AVERAGE))),
+			_ => ()
+		}
+		if options.sliding_window.is_some() {
+			// Each of these picks offsets from the whole image, which the window would then move out from under them.
+			if options.seed.is_some() || options.escape || options.max_offset.is_some() || !options.regions.is_empty() || !options.avoid_bytes.is_empty() {
+				return Err(MetastegError::UnsupportedFeature("a sliding window can't be combined with a seed, escapes, a maximum offset, regions or avoided bytes".to_string()));
+			}
+		}
+		if options.monotonic {
+			// Each of these picks offsets some other way, or leaves out occurrences the ordering might need.
+			if options.seed.is_some() || options.sliding_window.is_some() || options.escape || options.max_offset.is_some() || !options.regions.is_empty() || !options.avoid_bytes.is_empty() {
+				return Err(MetastegError::UnsupportedFeature("monotonic offsets can't be combined with a seed, a sliding window, escapes, a maximum offset, regions or avoided bytes".to_string()));
+			}
+		}
+		if options.limit_occurrences == Some(0) {
+			return Err(MetastegError::UnsupportedFeature("at least one occurrence of each byte value has to be kept".to_string()));
+		}
+		let mut header = Header::new();
+		header.width = options.width;
+		header.max_offset = options.max_offset;
+		header.disguise = options.disguise;
+		if options.permute_key.is_some() {
+			header.flags |= FLAG_PERMUTED;
+		}
+		if options.compress_offsets {
+			header.flags |= FLAG_COMPRESSED_OFFSETS;
+		}
+		if options.compress_payload {
+			header.flags |= FLAG_COMPRESSED_PAYLOAD;
+		}
+		header.flags |= options.format.flag();
+		if options.archive {
+			header.flags |= FLAG_ARCHIVE;
+		}
+		header.content_type = options.content_type.clone();
+		header.regions = options.regions.clone();
+		header.transform = options.transform;
+		header.sliding_window = options.sliding_window;
+		if options.escape {
+			// The sentinel must never be produced by a real offset, or escapes would be ambiguous.
+			let sentinel = sentinel_for_width(options.width);
+			if oracle.values().any(|offset| *offset == sentinel) {
+				return Err(MetastegError::SentinelCollision(sentinel));
+			}
+			header.sentinel = Some(sentinel);
+		}
+		Ok(Encoder { oracle, candidates: None, header, options: options.clone(), image_len: None, position: 0, occurrences: None, header_written: false })
+	}
+
+	// The oracle used to translate payload bytes into offsets.
+	pub fn oracle(&self) -> &BTreeMap<u8, u32> {
+		&self.oracle
+	}
+
+	// Check the oracle covers every byte value, so any payload can be encoded, unless escapes will take care of missing values.
+	// Oracles restricted by regions or forbidden bytes don't have to cover every value, so this lets them be checked up front.
+	pub fn check_coverage(&self) -> Result<(),MetastegError> {
+		if self.header.sentinel.is_some() {
+			return Ok(());
+		}
+		match (0..=255u8).find(|byte| !self.oracle.contains_key(byte)) {
+			Some(byte) => Err(MetastegError::MissingByteInImage { byte, max_offset: self.options.max_offset }),
+			None => Ok(())
+		}
+	}
+
+	// The header that will be written in front of the offsets.
+	pub fn header(&self) -> &Header {
+		&self.header
+	}
+
+	// Translate payload bytes into offsets with the oracle, without serializing them.
+	// With a seed, each byte is instead translated into a random one of its allowed offsets. The same seed always gives the same offsets.
+	pub fn encode_offsets(&self, payload: &[u8]) -> Result<Offsets,MetastegError> {
+		self.encode_offsets_at(payload, 0, &mut None)
+	}
+
+	// Translate payload bytes into offsets, for a payload that starts at this position in the whole payload.
+	// Only a sliding window depends on the position. If there is a hasher, the payload is fed to it as it is encoded.
+	fn encode_offsets_at(&self, payload: &[u8], start: usize, hasher: &mut Option<Sha256>) -> Result<Offsets,MetastegError> {
+		#[cfg(feature = "profile")]
+		let _span = tracing::info_span!("encode").entered();
+		if let Some(minimum) = self.options.min_occurrences {
+			self.check_occurrences(payload, minimum)?;
+		}
+		if let Some(window) = self.options.sliding_window {
+			return self.encode_offsets_sliding(payload, start, window, hasher);
+		}
+		if self.options.monotonic {
+			return self.encode_offsets_monotonic(payload, hasher);
+		}
+		if let Some(seed) = &self.options.seed {
+			return self.encode_offsets_seeded(payload, seed, hasher);
+		}
+		let mut encoded = Offsets::new();
+		for piece in hashed_pieces(payload, hasher) {
+			match self.header.sentinel {
+				Some(sentinel) => encoded.extend(metasteg_encode_escaped(piece, &self.oracle, sentinel).into_vec()),
+				None => encoded.extend(metasteg_encode(piece, &self.oracle).map_err(|e| self.unencodable(e))?.into_vec())
+			}
+		}
+		Ok(encoded)
+	}
+
+	// Without a block size every byte gets its own random pick. With one, each block of the payload gets its own oracle instead:
+	// a random offset for each byte value, chosen the first time the value occurs in the block, with the generator seeded from the seed and block index.
+	// Content-defined chunks work like blocks, but their generators are seeded from the chunk's bytes, so an edit only changes the offsets of the chunks it touches.
+	fn encode_offsets_seeded(&self, payload: &[u8], seed: &str, hasher: &mut Option<Sha256>) -> Result<Offsets,MetastegError> {
+		let candidates = match &self.candidates {
+			Some(x) => x,
+			None => return Err(MetastegError::UnsupportedFeature("random offsets need the image, so the encoder must be created with Encoder::new".to_string()))
+		};
+		let blocks : Vec<&[u8]> = match (self.options.chunk_average, self.options.block_size) {
+			(Some(average), _) => chunk::chunks(payload, average).collect(),
+			(None, block_size) => payload.chunks(block_size.unwrap_or(payload.len()).max(1)).collect()
+		};
+		let per_block = self.options.chunk_average.is_some() || self.options.block_size.is_some();
+		let mut encoded = Offsets::new();
+		for (index, block) in blocks.into_iter().enumerate() {
+			let mut rng = match (self.options.chunk_average, self.options.block_size) {
+				(Some(_), _) => SeededRng::for_chunk(self.options.rng, seed, block),
+				(None, Some(_)) => SeededRng::for_block(self.options.rng, seed, index as u64),
+				(None, None) => SeededRng::new(self.options.rng, seed)
+			};
+			let mut block_oracle : BTreeMap<u8, u32> = BTreeMap::new();
+			for byte in hashed_pieces(block, hasher).flatten() {
+				match (candidates.get(byte), self.header.sentinel) {
+					(Some(offsets), _) if per_block => {
+						let offset = *block_oracle.entry(*byte).or_insert_with(|| offsets[rng.below(offsets.len() as u64) as usize]);
+						encoded.push(offset);
+					},
+					(Some(offsets), _) => encoded.push(offsets[rng.below(offsets.len() as u64) as usize]),
+					(None, Some(sentinel)) => {
+						encoded.push(sentinel);
+						encoded.push(*byte as u32);
+					},
+					(None, None) => return Err(self.unencodable(*byte))
+				}
+			}
+		}
+		Ok(encoded)
+	}
+
+	// Check every byte value in the payload occurs at least the minimum number of times, listing those that don't.
+	fn check_occurrences(&self, payload: &[u8], minimum: u64) -> Result<(),MetastegError> {
+		let occurrences = match &self.occurrences {
+			Some(x) => x,
+			None => return Err(MetastegError::UnsupportedFeature("a minimum number of occurrences needs the image, so the encoder must be created with Encoder::new".to_string()))
+		};
+		let rare = find_rare(payload, occurrences, minimum);
+		match rare.is_empty() {
+			true => Ok(()),
+			false => Err(MetastegError::TooFewOccurrences { minimum, rare })
+		}
+	}
+
+	// Encode each byte as its first occurrence inside the window at its position, relative to the window's base.
+	fn encode_offsets_sliding(&self, payload: &[u8], start: usize, window: SlidingWindow, hasher: &mut Option<Sha256>) -> Result<Offsets,MetastegError> {
+		let (candidates, image_len) = match (&self.candidates, self.image_len) {
+			(Some(x), Some(image_len)) => (x, self.options.transform.map_or(image_len, |transform| transform.output_length(image_len as u64) as usize)),
+			_ => return Err(MetastegError::UnsupportedFeature("a sliding window needs the image, so the encoder must be created with Encoder::new".to_string()))
+		};
+		let mut encoded = Offsets::new();
+		for (i, byte) in hashed_pieces(payload, hasher).flatten().enumerate() {
+			let base = window.base(start + i, image_len)?;
+			let offsets = candidates.get(byte).map_or(&[][..], |x| x.as_slice());
+			match offsets.get(offsets.partition_point(|offset| *offset < base)) {
+				Some(offset) if offset - base < window.size => encoded.push(offset - base),
+				_ => return Err(MetastegError::NoOffsetInSlidingWindow { byte: *byte, position: start + i })
+			}
+		}
+		Ok(encoded)
+	}
+
+	// Encode each byte as its first occurrence after the offset chosen for the byte before it.
+	// Taking the earliest occurrence every time leaves as much of the image as possible for the rest of the payload,
+	// so this only fails if no strictly increasing sequence of offsets spells out the payload.
+	fn encode_offsets_monotonic(&self, payload: &[u8], hasher: &mut Option<Sha256>) -> Result<Offsets,MetastegError> {
+		let candidates = match &self.candidates {
+			Some(x) => x,
+			None => return Err(MetastegError::UnsupportedFeature("monotonic offsets need the image, so the encoder must be created with Encoder::new".to_string()))
+		};
+		let mut encoded = Offsets::new();
+		let mut previous : Option<u32> = None;
+		for (i, byte) in hashed_pieces(payload, hasher).flatten().enumerate() {
+			let offsets = candidates.get(byte).map_or(&[][..], |x| x.as_slice());
+			let next = match previous {
+				Some(after) => offsets.partition_point(|offset| *offset <= after),
+				None => 0
+			};
+			match (offsets.get(next), previous) {
+				(Some(offset), _) => {
+					encoded.push(*offset);
+					previous = Some(*offset);
+				},
+				(None, Some(after)) => return Err(MetastegError::NoMonotonicOffset { byte: *byte, position: i, after }),
+				(None, None) => return Err(self.unencodable(*byte))
+			}
+		}
+		Ok(encoded)
+	}
+
+	// The error for a payload byte that the oracle can't translate, explaining why if the options restricted it.
+	fn unencodable(&self, byte: u8) -> MetastegError {
+		if is_constrained(&self.options.regions, byte) {
+			MetastegError::NoOffsetInRegion(byte)
+		} else if !self.options.avoid_bytes.is_empty() {
+			MetastegError::NoAllowedOffset(byte)
+		} else {
+			MetastegError::UnencodableByte(byte)
+		}
+	}
+
+	// Encode a whole payload into a complete container (header and offsets).
+	// Unlike update, this can be called any number of times and doesn't affect the stream.
+	// If the options select a range of the payload, only that range is encoded (and checksummed).
+	pub fn encode_container(&self, payload: &[u8]) -> Result<Vec<u8>,MetastegError> {
+		Ok(self.encode_container_with_report(payload)?.0)
+	}
+
+	// Encode a whole payload like encode_container, and also report on the result.
+	pub fn encode_container_with_report(&self, payload: &[u8]) -> Result<(Vec<u8>, EncodeReport),MetastegError> {
+		let payload_len = payload.len();
+		let mut header = self.header.clone();
+		let payload = match (self.options.payload_offset, self.options.payload_length) {
+			(None, None) => payload,
+			(offset, length) => {
+				let range = select_range(payload.len(), offset, length)?;
+				header.payload_range = Some((range.start as u64, range.len() as u64));
+				&payload[range]
+			}
+		};
+		if header.content_type.is_none() && self.options.sniff_content_type {
+			header.content_type = sniff_content_type(payload);
+		}
+		// The checksum always covers the original payload, so it can be checked after inflating.
+		// Unless the payload is compressed first, that is the payload being encoded, so it is hashed in the same pass as the encode.
+		let mut hasher = None;
+		if self.options.checksum {
+			match self.options.compress_payload {
+				true => header.checksum = Some(sha256(payload)),
+				false => hasher = Some(Sha256::new())
+			}
+		}
+		let compressed;
+		let payload = if self.options.compress_payload {
+			compressed = deflate(payload);
+			&compressed
+		} else {
+			payload
+		};
+		let mut offsets = self.encode_offsets_at(payload, 0, &mut hasher)?;
+		if let Some(hasher) = hasher {
+			header.checksum = Some(hasher.finalize().into());
+		}
+		let real_offsets = image_offsets(&header, &offsets);
+		let mut report = EncodeReport {
+			payload_len,
+			image_len: self.image_len,
+			offsets: offsets.len(),
+			container_len: 0,
+			coverage: self.oracle.len(),
+			distinct_offsets: real_offsets.iter().collect::<BTreeSet<&u32>>().len()
+		};
+		#[cfg(feature = "profile")]
+		let _span = tracing::info_span!("serialize").entered();
+		if let Some(key) = &self.options.permute_key {
+			offsets = Offsets::from(permute(&offsets, key));
+		}
+		let mut serialized_offsets = codec_for(&header).serialize_to_vec(&offsets)?;
+		if self.options.compress_offsets {
+			serialized_offsets = deflate(&serialized_offsets);
+		}
+		if let Some(key) = &self.options.mac_key {
+			header.mac = Some(container_mac(key, &header, &serialized_offsets)?);
+		}
+		let mut container = serialize_header(&header)?;
+		container.extend(serialized_offsets);
+		report.container_len = container.len();
+		Ok((container, report))
+	}
+
+	// Check the options allow encoding as a stream, since some of them depend on the whole payload.
+	pub fn check_streamable(&self) -> Result<(),MetastegError> {
+		check_streamable(self.header.flags)?;
+		if self.options.checksum {
+			return Err(MetastegError::UnsupportedFeature("checksummed containers can't be encoded as a stream".to_string()));
+		}
+		if self.options.mac_key.is_some() {
+			return Err(MetastegError::UnsupportedFeature("authenticated containers can't be encoded as a stream".to_string()));
+		}
+		if self.options.sniff_content_type && self.options.content_type.is_none() {
+			return Err(MetastegError::UnsupportedFeature("content types can't be sniffed when encoding as a stream".to_string()));
+		}
+		if self.options.payload_offset.is_some() || self.options.payload_length.is_some() {
+			return Err(MetastegError::UnsupportedFeature("payload ranges can't be encoded as a stream".to_string()));
+		}
+		if self.options.seed.is_some() {
+			return Err(MetastegError::UnsupportedFeature("random offsets can't be encoded as a stream".to_string()));
+		}
+		if self.options.monotonic {
+			// Each chunk would have to carry on from the last offset of the one before, which a resumed stream doesn't know.
+			return Err(MetastegError::UnsupportedFeature("monotonic offsets can't be encoded as a stream".to_string()));
+		}
+		Ok(())
+	}
+
+	// Encode the next chunk of the payload, returning the serialized bytes to append to the container.
+	pub fn update(&mut self, payload: &[u8]) -> Result<Vec<u8>,MetastegError> {
+		self.check_streamable()?;
+		let encoded_payload = self.encode_offsets_at(payload, self.position, &mut None)?;
+		self.position += payload.len();
+		let serialized_offsets = encoded_payload.serialize(self.header.width)?;
+		let mut serialized = self.take_header()?;
+		serialized.extend(serialized_offsets);
+		Ok(serialized)
+	}
+
+	// Treat the header as already written, when carrying on with a stream whose header was written earlier.
+	pub fn skip_header(&mut self) {
+		self.header_written = true;
+	}
+
+	// Carry on a stream from this many payload bytes in, when the bytes before them were encoded earlier.
+	pub fn skip_payload(&mut self, length: usize) {
+		self.position += length;
+	}
+
+	// Finish encoding, returning any bytes that still need to be written (the header, for an empty payload).
+	pub fn finish(mut self) -> Result<Vec<u8>,MetastegError> {
+		self.take_header()
+	}
+
+	fn take_header(&mut self) -> Result<Vec<u8>,MetastegError> {
+		if self.header_written {
+			return Ok(Vec::new());
+		}
+		self.header_written = true;
+		serialize_header(&self.header)
+	}
+}
+
+// Decodes a container chunk by chunk, yielding payload bytes as soon as whole offsets are available.
+// Bytes belonging to a partial header or offset are buffered until the rest of them arrive.
+// A checksum can only be verified once the whole payload has been seen, so it is checked by finish.
+pub struct Decoder {
+	image: Vec<u8>,
+	options: DecodeOptions,
+	header: Option<Header>,
+	pending: Vec<u8>,
+	escaped: bool,
+	// How many offsets have been decoded, which a sliding window moves with.
+	position: usize,
+	hasher: Sha256
+}
+
+impl Decoder {
+	pub fn new(image: Vec<u8>) -> Decoder {
+		Decoder::with_options(image, DecodeOptions::default())
+	}
+
+	pub fn with_options(image: Vec<u8>, options: DecodeOptions) -> Decoder {
+		Decoder { image, options, header: None, pending: Vec::new(), escaped: false, position: 0, hasher: Sha256::new() }
+	}
+
+	// The container header, once enough of the container has been seen to parse it.
+	pub fn header(&self) -> Option<&Header> {
+		self.header.as_ref()
+	}
+
+	// Feed the next chunk of the container, returning any payload bytes that could be decoded.
+	pub fn update(&mut self, chunk: &[u8]) -> Result<Vec<u8>,MetastegError> {
+		self.pending.extend_from_slice(chunk);
+		if self.header.is_none() {
+			let (header, body_start) = match parse_header_partial(&self.pending)? {
+				Some(x) => x,
+				None => return Ok(Vec::new())
+			};
+			check_declared_width(&header, &self.options)?;
+			check_streamable(header.flags)?;
+			// The MAC covers every offset, so it could only be checked once the payload had already been handed out.
+			// A key is refused too, so a container without a MAC can't stream past a caller expecting one.
+			if header.mac.is_some() || self.options.mac_key.is_some() {
+				return Err(MetastegError::UnsupportedFeature("authenticated containers can't be streamed".to_string()));
+			}
+			check_fingerprint(&header, &self.image)?;
+			check_images(&header, &[&self.image])?;
+			// Offsets beyond the end of the image will already fail to decode, so only the length needs checking up front.
+			if self.options.strict_offsets {
+				check_image_length(&header, self.image.len())?;
+			}
+			if let Some(transform) = header.transform {
+				self.image = transform.apply(&self.image);
+			}
+			self.pending.drain(..body_start);
+			self.header = Some(header);
+		}
+		let header = self.header.as_ref().unwrap();
+		let complete = self.pending.len() - self.pending.len() % header.width as usize;
+		let mut offsets = Offsets::deserialize(&self.pending[..complete], header.width)?;
+		self.pending.drain(..complete);
+		if let Some(window) = header.sliding_window {
+			offsets = window.to_absolute(&offsets, self.position, self.image.len())?;
+			self.position += offsets.len();
+		}
+		let decoded = decode_offsets(header, &offsets, &self.image, &mut self.escaped)?;
+		self.hasher.update(&decoded);
+		Ok(decoded)
+	}
+
+	// Finish decoding, checking that the container didn't end partway through a header or offset,
+	// and that the decoded payload matches the checksum if there is one.
+	pub fn finish(self) -> Result<(),MetastegError> {
+		let header = match self.header {
+			Some(x) => x,
+			None if self.pending.is_empty() => return Ok(()),
+			None => return Err(MetastegError::InvalidHeader("header is truncated".to_string()))
+		};
+		check_length(self.pending.len(), header.width)?;
+		check_escape_complete(self.escaped)?;
+		match header.checksum {
+			Some(checksum) if <[u8;32]>::from(self.hasher.finalize()) != checksum => Err(MetastegError::ChecksumMismatch),
+			_ => Ok(())
+		}
+	}
+}
@@ -0,0 +1,65 @@
+// Deterministic transforms applied to the image before it is used, for covers that will have been transformed by the time they are decoded.
+// The offsets then index into the transformed bytes, and the transform is recorded in the header so decode can apply it too.
+use std::borrow::Cow;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transform {
+	// Treat the image as packed 8-bit RGB pixels and replace each pixel with its luma (ITU-R BT.601 weights).
+	Grayscale,
+	// Replace each pair of bytes with their average, halving the length.
+	Downsample
+}
+
+pub const TRANSFORMS : [Transform;2] = [Transform::Grayscale, Transform::Downsample];
+
+impl Transform {
+	pub fn from_name(name: &str) -> Option<Transform> {
+		TRANSFORMS.iter().find(|transform| transform.name() == name).copied()
+	}
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			Transform::Grayscale => "grayscale",
+			Transform::Downsample => "downsample"
+		}
+	}
+
+	// The identifier recorded in the container header.
+	pub fn id(&self) -> u8 {
+		match self {
+			Transform::Grayscale => 1,
+			Transform::Downsample => 2
+		}
+	}
+
+	pub fn from_id(id: u8) -> Option<Transform> {
+		TRANSFORMS.iter().find(|transform| transform.id() == id).copied()
+	}
+
+	// The length of an image of the given length after the transform.
+	pub fn output_length(&self, length: u64) -> u64 {
+		match self {
+			Transform::Grayscale => length / 3,
+			Transform::Downsample => length / 2
+		}
+	}
+
+	// Transform an image. Any bytes left over at the end that don't make up a whole pixel or pair are dropped.
+	pub fn apply(&self, image: &[u8]) -> Vec<u8> {
+		match self {
+			Transform::Grayscale => image.chunks_exact(3).map(|rgb| {
+				let luma = 299 * rgb[0] as u32 + 587 * rgb[1] as u32 + 114 * rgb[2] as u32;
+				((luma + 500) / 1000) as u8
+			}).collect(),
+			Transform::Downsample => image.chunks_exact(2).map(|pair| ((pair[0] as u16 + pair[1] as u16) / 2) as u8).collect()
+		}
+	}
+}
+
+// Apply a transform if there is one, borrowing the image unchanged if there isn't.
+pub fn transform_image(transform: Option<Transform>, image: &[u8]) -> Cow<'_, [u8]> {
+	match transform {
+		Some(x) => Cow::Owned(x.apply(image)),
+		None => Cow::Borrowed(image)
+	}
+}
@@ -0,0 +1,40 @@
+use metastego::MetastegError;
+use metastego::archive::{Entry, pack, unpack};
+
+fn entry(name: &str, data: &[u8]) -> Entry {
+	Entry { name: name.to_string(), data: data.to_vec() }
+}
+
+// Pack a single entry by hand, so names that pack refuses can still be tested.
+fn raw_entry(name: &str, data: &[u8], checksum: [u8;32]) -> Vec<u8> {
+	let mut packed = (name.len() as u16).to_be_bytes().to_vec();
+	packed.extend_from_slice(name.as_bytes());
+	packed.extend_from_slice(&(data.len() as u64).to_be_bytes());
+	packed.extend_from_slice(&checksum);
+	packed.extend_from_slice(data);
+	packed
+}
+
+#[test]
+fn archive_round_trip() {
+	let entries = vec![entry("a.txt", b"first"), entry("empty", b""), entry("b.bin", &[0, 1, 2, 255])];
+	let unpacked = unpack(&pack(&entries).unwrap()).unwrap();
+	assert_eq!(unpacked.into_iter().map(|(entry, _)| entry).collect::<Vec<Entry>>(), entries);
+}
+
+#[test]
+fn unsafe_names_are_rejected() {
+	for name in ["../escape", "/etc/passwd", "a/b", "..\\windows", "..", ""] {
+		assert!(matches!(pack(&[entry(name, b"x")]), Err(MetastegError::InvalidArchive(_))), "{}", name);
+		let packed = raw_entry(name, b"x", metastego::sha256(b"x"));
+		assert!(matches!(unpack(&packed), Err(MetastegError::InvalidArchive(_))), "{}", name);
+	}
+}
+
+#[test]
+fn corrupt_entries_are_rejected() {
+	let packed = raw_entry("a.txt", b"data", [0u8;32]);
+	assert!(matches!(unpack(&packed), Err(MetastegError::InvalidArchive(_))));
+	let packed = pack(&[entry("a.txt", b"data")]).unwrap();
+	assert!(matches!(unpack(&packed[..packed.len() - 1]), Err(MetastegError::InvalidArchive(_))));
+}
@@ -0,0 +1,45 @@
+use metastego::{EncodeOptions, encode_bytes};
+use metastego::audit::Manifest;
+
+const KEY : [u8;32] = [7u8;32];
+
+fn encode() -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+	let payload = b"audited payload".to_vec();
+	let image : Vec<u8> = (0..=255u8).collect();
+	let container = encode_bytes(&payload, &image, &EncodeOptions { checksum: true, ..EncodeOptions::default() }).unwrap();
+	(payload, image, container)
+}
+
+#[test]
+fn manifest_round_trips() {
+	let (payload, image, container) = encode();
+	let mut manifest = Manifest::new(&payload, &image, &container).unwrap();
+	assert_eq!(manifest.to_json(), Manifest::new(&payload, &image, &container).unwrap().to_json());
+	assert_eq!(Manifest::parse(&manifest.to_json()).unwrap(), manifest);
+	manifest.sign(&KEY);
+	let parsed = Manifest::parse(&manifest.to_json()).unwrap();
+	assert_eq!(parsed, manifest);
+	parsed.verify(&payload, &image, &container, None).unwrap();
+	parsed.verify(&payload, &image, &container, Some(&parsed.signature.unwrap().0)).unwrap();
+	assert!(Manifest::parse("{}").is_err());
+}
+
+#[test]
+fn verify_catches_changes() {
+	let (payload, image, container) = encode();
+	let mut manifest = Manifest::new(&payload, &image, &container).unwrap();
+	manifest.sign(&KEY);
+	let mut other_image = image.clone();
+	other_image.reverse();
+	assert!(manifest.verify(b"another payload", &image, &container, None).is_err());
+	assert!(manifest.verify(&payload, &other_image, &container, None).is_err());
+	assert!(manifest.verify(&payload, &image, &container[..container.len() - 1], None).is_err());
+	// Wrong signer, or a manifest edited after it was signed.
+	assert!(manifest.verify(&payload, &image, &container, Some(&[1u8;32])).is_err());
+	let tampered = Manifest { tool_version: "0.0.0".to_string(), ..manifest.clone() };
+	assert!(tampered.verify(&payload, &image, &container, None).is_err());
+	// An unsigned manifest passes only when no signer is required.
+	let unsigned = Manifest { signature: None, ..manifest };
+	unsigned.verify(&payload, &image, &container, None).unwrap();
+	assert!(unsigned.verify(&payload, &image, &container, Some(&[1u8;32])).is_err());
+}
@@ -0,0 +1,25 @@
+use metastego::{EncodeOptions, DecodeOptions, MetastegError, encode_bytes, decode_bytes};
+use metastego::header::parse_header;
+
+mod common;
+use common::hashed_image;
+
+#[test]
+fn offsets_avoid_forbidden_bytes() {
+	let image = hashed_image(4096);
+	let payload = b"safe for text transports\n".to_vec();
+	let options = EncodeOptions { width: 2, avoid_bytes: vec![0x00, 0x0a, 0x0d], ..EncodeOptions::default() };
+	let container = encode_bytes(&payload, &image, &options).unwrap();
+	let (_, body_start) = parse_header(&container).unwrap();
+	assert!(!container[body_start..].iter().any(|x| [0x00, 0x0a, 0x0d].contains(x)));
+	assert_eq!(decode_bytes(&container, &image, &DecodeOptions::default()).unwrap(), payload);
+}
+
+#[test]
+fn missing_allowed_offset_is_an_error() {
+	// Every offset in a 256-byte image has a zero high byte at width 2.
+	let image : Vec<u8> = (0..=255u8).collect();
+	let options = EncodeOptions { width: 2, avoid_bytes: vec![0x00], ..EncodeOptions::default() };
+	let result = encode_bytes(b"x", &image, &options);
+	assert!(matches!(result, Err(MetastegError::NoAllowedOffset(b'x'))));
+}
@@ -0,0 +1,28 @@
+use metastego::{MetastegError, EncodeOptions, DecodeOptions, encode_bytes, decode_bytes};
+use metastego::bundle::{bundle, unbundle};
+
+#[test]
+fn bundle_round_trip() {
+	let image : Vec<u8> = (0..=255u8).rev().collect();
+	let payload = b"image and container in one file".to_vec();
+	let container = encode_bytes(&payload, &image, &EncodeOptions::default()).unwrap();
+	let bundled = bundle(&image, &container);
+	assert!(bundled.starts_with(&image));
+
+	let (bundled_image, bundled_container) = unbundle(&bundled).unwrap();
+	assert_eq!(bundled_image, image.as_slice());
+	assert_eq!(bundled_container, container.as_slice());
+	assert_eq!(decode_bytes(bundled_container, bundled_image, &DecodeOptions::default()).unwrap(), payload);
+}
+
+#[test]
+fn bad_trailers_are_rejected() {
+	let bundled = bundle(b"image", b"container");
+	// Cut off partway through the trailer.
+	assert!(matches!(unbundle(&bundled[..bundled.len() - 1]), Err(MetastegError::InvalidBundle(_))));
+	// A length longer than everything before the trailer.
+	let mut oversized = bundled.clone();
+	let length_at = oversized.len() - 16;
+	oversized[length_at..length_at + 8].copy_from_slice(&u64::MAX.to_be_bytes());
+	assert!(matches!(unbundle(&oversized), Err(MetastegError::InvalidBundle(_))));
+}
@@ -0,0 +1,28 @@
+use metastego::{EncodeOptions, Container, encode_bytes, decode_bytes, sha256};
+use metastego::sliding::SlidingWindow;
+
+#[test]
+fn checksum_hashed_during_encode_matches_the_payload() {
+	let image : Vec<u8> = (0..=255u8).cycle().take(1 << 16).collect();
+	// Long enough to be hashed in several pieces.
+	let payload : Vec<u8> = (0..140_000u32).map(|x| (x.wrapping_mul(2654435761) >> 11) as u8).collect();
+	let checksummed = EncodeOptions { checksum: true, ..EncodeOptions::default() };
+	let seeded = EncodeOptions { seed: Some("seed".to_string()), ..checksummed.clone() };
+	for options in [
+		checksummed.clone(),
+		EncodeOptions { escape: true, ..checksummed.clone() },
+		EncodeOptions { compress_payload: true, ..checksummed.clone() },
+		EncodeOptions { sliding_window: Some(SlidingWindow { size: 4096, advance: 3 }), ..checksummed.clone() },
+		seeded.clone(),
+		EncodeOptions { block_size: Some(1000), ..seeded.clone() },
+		EncodeOptions { chunk_average: Some(4096), ..seeded.clone() }
+	] {
+		let container = encode_bytes(&payload, &image, &options).unwrap();
+		assert_eq!(Container::parse(&container).unwrap().header.checksum, Some(sha256(&payload)));
+		assert_eq!(decode_bytes(&container, &image, &Default::default()).unwrap(), payload);
+	}
+	// With a range, only the part encoded is hashed.
+	let options = EncodeOptions { payload_offset: Some(10), payload_length: Some(70_000), ..checksummed };
+	let container = encode_bytes(&payload, &image, &options).unwrap();
+	assert_eq!(Container::parse(&container).unwrap().header.checksum, Some(sha256(&payload[10..70_010])));
+}
@@ -0,0 +1,59 @@
+use metastego::{EncodeOptions, Container, encode_bytes, decode_bytes};
+use metastego::chunk::{chunks, chunk_bounds};
+
+mod common;
+use common::noise;
+
+// The offset at which each chunk ends.
+fn boundaries(data: &[u8], average: usize) -> Vec<usize> {
+	chunks(data, average).scan(0, |end, chunk| {
+		*end += chunk.len();
+		Some(*end)
+	}).collect()
+}
+
+#[test]
+fn chunks_stay_within_bounds() {
+	let data = noise(1 << 16, 1);
+	let (min, max) = chunk_bounds(1024);
+	let lengths : Vec<usize> = chunks(&data, 1024).map(|chunk| chunk.len()).collect();
+	assert_eq!(lengths.iter().sum::<usize>(), data.len());
+	assert!(lengths[..lengths.len() - 1].iter().all(|length| (min..=max).contains(length)));
+	// The sizes should land near the average, not at either bound.
+	let mean = data.len() / lengths.len();
+	assert!((512..2048).contains(&mean), "{}", mean);
+	assert!(chunks(&[], 1024).next().is_none());
+}
+
+#[test]
+fn boundaries_survive_an_insertion() {
+	let data = noise(1 << 16, 2);
+	let mut edited = data.clone();
+	edited.splice(100..100, *b"inserted");
+	let before = boundaries(&data, 1024);
+	// Past the first few chunks, every boundary is in the same place, shifted by the insertion.
+	let after : Vec<usize> = boundaries(&edited, 1024).into_iter().map(|end| end - 8).collect();
+	let shared = before.iter().filter(|end| after.contains(end)).count();
+	assert!(shared >= before.len() - 2, "{} of {}", shared, before.len());
+}
+
+#[test]
+fn seeded_chunks_only_change_where_edited() {
+	let image : Vec<u8> = (0..=255u8).cycle().take(1 << 14).collect();
+	let payload = noise(1 << 14, 3);
+	let options = EncodeOptions { seed: Some("seed".to_string()), chunk_average: Some(256), ..EncodeOptions::default() };
+	let container = encode_bytes(&payload, &image, &options).unwrap();
+	assert_eq!(decode_bytes(&container, &image, &Default::default()).unwrap(), payload);
+
+	// Changing one byte near the start leaves the offsets of the later chunks alone.
+	let mut edited = payload.clone();
+	edited[10] ^= 0xff;
+	let (original, changed) = (Container::parse(&container).unwrap().offsets, Container::parse(&encode_bytes(&edited, &image, &options).unwrap()).unwrap().offsets);
+	let differing = original.iter().zip(changed.iter()).filter(|(a, b)| a != b).count();
+	assert!(differing > 0);
+	assert_eq!(original[payload.len() / 2..], changed[payload.len() / 2..]);
+
+	for bad in [EncodeOptions { seed: None, ..options.clone() }, EncodeOptions { block_size: Some(16), ..options.clone() }, EncodeOptions { chunk_average: Some(8), ..options.clone() }] {
+		assert!(encode_bytes(&payload, &image, &bad).is_err());
+	}
+}
@@ -0,0 +1,363 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+mod common;
+use common::noise;
+
+// A scratch directory for one test's files, emptied before the test runs.
+fn scratch(name: &str) -> PathBuf {
+	let dir = std::env::temp_dir().join(format!("metastego-cli-{}-{}", name, std::process::id()));
+	let _ = fs::remove_dir_all(&dir);
+	fs::create_dir_all(&dir).unwrap();
+	dir
+}
+
+fn metastego(args: &[&str]) -> String {
+	let output = Command::new(env!("CARGO_BIN_EXE_metastego")).args(args).output().unwrap();
+	String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn failed_coverage_leaves_no_output() {
+	let dir = scratch("coverage");
+	let payload = dir.join("payload.bin");
+	let image = dir.join("image.bin");
+	let output = dir.join("output.bin");
+	fs::write(&payload, b"payload").unwrap();
+	// Every byte value but 0xff.
+	fs::write(&image, (0..255u8).collect::<Vec<u8>>()).unwrap();
+
+	let stdout = metastego(&["encode", payload.to_str().unwrap(), output.to_str().unwrap(), image.to_str().unwrap()]);
+	assert!(stdout.starts_with("Failed to encode"));
+	assert!(!output.exists());
+
+	// A constrained oracle can encode this payload, but --check-image insists on full coverage first.
+	for extra in [vec!["--regions", "00-ff:0-255"], vec!["--regions", "00-ff:0-255", "--checkpoint"]] {
+		let mut args = vec!["encode", payload.to_str().unwrap(), output.to_str().unwrap(), image.to_str().unwrap(), "--check-image"];
+		args.extend(extra);
+		let stdout = metastego(&args);
+		assert!(stdout.starts_with("Failed to encode"), "{}", stdout);
+		assert!(!output.exists());
+	}
+
+	// A checksum can't be streamed, which is found out before the output is created.
+	fs::write(&image, (0..=255u8).collect::<Vec<u8>>()).unwrap();
+	let stdout = metastego(&["encode", payload.to_str().unwrap(), output.to_str().unwrap(), image.to_str().unwrap(), "--checkpoint", "--checksum"]);
+	assert!(stdout.starts_with("Failed to encode"), "{}", stdout);
+	assert!(!output.exists());
+	fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn bench_image_exit_status() {
+	let dir = scratch("bench");
+	let payload = dir.join("payload.bin");
+	let image = dir.join("image.bin");
+	fs::write(&payload, b"payload").unwrap();
+	fs::write(&image, (0..=255u8).collect::<Vec<u8>>()).unwrap();
+
+	let bench = |image: &PathBuf| Command::new(env!("CARGO_BIN_EXE_metastego")).args(["bench-image", payload.to_str().unwrap(), image.to_str().unwrap()]).output().unwrap();
+	let output = bench(&image);
+	assert!(output.status.success());
+	assert!(String::from_utf8(output.stdout).unwrap().starts_with("Fully encodes: yes"));
+
+	// Without 'y' the payload can't be encoded.
+	fs::write(&image, (0..=255u8).filter(|x| *x != b'y').collect::<Vec<u8>>()).unwrap();
+	let output = bench(&image);
+	assert!(!output.status.success());
+	assert!(String::from_utf8(output.stdout).unwrap().starts_with("Fully encodes: no"));
+	fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn deterministic_encodes_are_identical() {
+	let dir = scratch("deterministic");
+	let payload = dir.join("payload");
+	let image = dir.join("image.bin");
+	fs::create_dir(&payload).unwrap();
+	fs::write(payload.join("b.txt"), b"second").unwrap();
+	fs::write(payload.join("a.txt"), b"first").unwrap();
+	fs::write(&image, (0..=255u8).cycle().take(4096).collect::<Vec<u8>>()).unwrap();
+
+	let encode = |output: &PathBuf, extra: &[&str]| {
+		let mut args = vec!["encode", payload.to_str().unwrap(), output.to_str().unwrap(), image.to_str().unwrap(), "--deterministic", "--checksum", "--compress"];
+		args.extend(extra);
+		metastego(&args)
+	};
+	let (first, second) = (dir.join("first.bin"), dir.join("second.bin"));
+	for extra in [vec![], vec!["--seed", "s", "--rng", "pcg", "--block-size", "2"]] {
+		encode(&first, &extra);
+		encode(&second, &extra);
+		assert_eq!(fs::read(&first).unwrap(), fs::read(&second).unwrap());
+	}
+
+	let stdout = encode(&first, &["--seed", "s"]);
+	assert!(stdout.contains("--deterministic requires --rng"), "{}", stdout);
+	fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn encode_then_decode_round_trips() {
+	let dir = scratch("round-trip");
+	let payload = dir.join("payload.bin");
+	let image = dir.join("image.bin");
+	let encoded = dir.join("encoded.bin");
+	let decoded = dir.join("decoded.bin");
+	let contents : Vec<u8> = (0..1000u32).map(|x| (x * 31 % 256) as u8).collect();
+	fs::write(&payload, &contents).unwrap();
+	fs::write(&image, (0..=255u8).rev().collect::<Vec<u8>>()).unwrap();
+
+	let stdout = metastego(&["encode", payload.to_str().unwrap(), encoded.to_str().unwrap(), image.to_str().unwrap(), "--checksum"]);
+	assert!(stdout.starts_with("Successfully encoded"), "{}", stdout);
+	let stdout = metastego(&["decode", encoded.to_str().unwrap(), decoded.to_str().unwrap(), image.to_str().unwrap()]);
+	assert!(stdout.starts_with("Successfully decoded"), "{}", stdout);
+	assert_eq!(fs::read(&decoded).unwrap(), contents);
+	fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn decode_with_wrong_image_fails_checksum() {
+	let dir = scratch("wrong-image");
+	let payload = dir.join("payload.bin");
+	let image = dir.join("image.bin");
+	let wrong_image = dir.join("wrong.bin");
+	let encoded = dir.join("encoded.bin");
+	let decoded = dir.join("decoded.bin");
+	fs::write(&payload, b"only the right image decodes this").unwrap();
+	fs::write(&image, (0..=255u8).collect::<Vec<u8>>()).unwrap();
+	fs::write(&wrong_image, (0..=255u8).rev().collect::<Vec<u8>>()).unwrap();
+
+	metastego(&["encode", payload.to_str().unwrap(), encoded.to_str().unwrap(), image.to_str().unwrap(), "--checksum"]);
+	let stdout = metastego(&["decode", encoded.to_str().unwrap(), decoded.to_str().unwrap(), wrong_image.to_str().unwrap()]);
+	assert!(stdout.starts_with("Failed to decode"), "{}", stdout);
+	assert!(!decoded.exists());
+	fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn checkpoints_at_content_defined_chunks() {
+	let dir = scratch("chunked-checkpoint");
+	let payload = dir.join("payload.bin");
+	let image = dir.join("image.bin");
+	let encoded = dir.join("encoded.bin");
+	let decoded = dir.join("decoded.bin");
+	let contents : Vec<u8> = (0..100_000u32).map(|x| (x.wrapping_mul(2654435761) >> 13) as u8).collect();
+	fs::write(&payload, &contents).unwrap();
+	fs::write(&image, (0..=255u8).collect::<Vec<u8>>()).unwrap();
+
+	let stdout = metastego(&["encode", payload.to_str().unwrap(), encoded.to_str().unwrap(), image.to_str().unwrap(), "--checkpoint", "--chunk-size", "4096"]);
+	assert!(stdout.starts_with("Successfully encoded"), "{}", stdout);
+	metastego(&["decode", encoded.to_str().unwrap(), decoded.to_str().unwrap(), image.to_str().unwrap()]);
+	assert_eq!(fs::read(&decoded).unwrap(), contents);
+
+	let stdout = metastego(&["encode", payload.to_str().unwrap(), encoded.to_str().unwrap(), image.to_str().unwrap(), "--chunk-size", "4096"]);
+	assert!(stdout.contains("--chunk-size requires --seed or --checkpoint"), "{}", stdout);
+	fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn audit_manifest_verifies() {
+	let dir = scratch("audit");
+	let payload = dir.join("payload.bin");
+	let image = dir.join("image.bin");
+	let encoded = dir.join("encoded.bin");
+	let manifest = dir.join("manifest.json");
+	let key = dir.join("key.hex");
+	fs::write(&payload, b"an audited payload").unwrap();
+	fs::write(&image, (0..=255u8).collect::<Vec<u8>>()).unwrap();
+	fs::write(&key, "11".repeat(32)).unwrap();
+
+	let stdout = metastego(&["encode", payload.to_str().unwrap(), encoded.to_str().unwrap(), image.to_str().unwrap(), "--audit", manifest.to_str().unwrap(), "--audit-key", key.to_str().unwrap()]);
+	assert!(stdout.starts_with("Successfully encoded"), "{}", stdout);
+	let verify = || metastego(&["verify-audit", manifest.to_str().unwrap(), payload.to_str().unwrap(), image.to_str().unwrap(), encoded.to_str().unwrap()]);
+	let stdout = verify();
+	assert!(stdout.contains("matches the files, and is signed by"), "{}", stdout);
+
+	fs::write(&payload, b"a different payload").unwrap();
+	let stdout = verify();
+	assert!(stdout.starts_with("Failed to verify"), "{}", stdout);
+	fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn single_byte_image_benches_cleanly() {
+	let dir = scratch("single-byte");
+	let payload = dir.join("payload.bin");
+	let image = dir.join("image.bin");
+	fs::write(&payload, b"aaaa").unwrap();
+	fs::write(&image, b"a").unwrap();
+
+	let stdout = metastego(&["bench-image", payload.to_str().unwrap(), image.to_str().unwrap()]);
+	assert!(stdout.contains("could not produce an offset for value 0x00"), "{}", stdout);
+	// One distinct offset has no entropy, and shouldn't be printed as -0.
+	let stdout = metastego(&["bench-image", payload.to_str().unwrap(), image.to_str().unwrap(), "--escape"]);
+	assert!(stdout.contains("Offset entropy: 0.000 bits"), "{}", stdout);
+	assert!(stdout.contains("Offset range: 0 to 0"), "{}", stdout);
+	fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn size_limits_refuse_large_files() {
+	let dir = scratch("size-limits");
+	let payload = dir.join("payload.bin");
+	let image = dir.join("image.bin");
+	let encoded = dir.join("encoded.bin");
+	fs::write(&payload, b"payload").unwrap();
+	fs::write(&image, (0..=255u8).collect::<Vec<u8>>()).unwrap();
+
+	let encode = |extra: &[&str]| {
+		let mut args = vec!["encode", payload.to_str().unwrap(), encoded.to_str().unwrap(), image.to_str().unwrap()];
+		args.extend(extra);
+		metastego(&args)
+	};
+	let stdout = encode(&["--max-image-bytes", "255"]);
+	assert!(stdout.contains("256 bytes is over the --max-image-bytes limit of 255"), "{}", stdout);
+	let stdout = encode(&["--max-payload-bytes", "6", "--checkpoint"]);
+	assert!(stdout.contains("7 bytes is over the --max-payload-bytes limit of 6"), "{}", stdout);
+	assert!(!encoded.exists());
+	let stdout = encode(&["--max-image-bytes", "256", "--max-payload-bytes", "7"]);
+	assert!(stdout.starts_with("Successfully encoded"), "{}", stdout);
+	fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn archived_containers_decode() {
+	let dir = scratch("package");
+	let payload = dir.join("payload.bin");
+	let image = dir.join("image.bin");
+	let wrong_image = dir.join("wrong.bin");
+	let packaged = dir.join("encoded.zip");
+	let decoded = dir.join("decoded.bin");
+	fs::write(&payload, b"a packaged payload").unwrap();
+	fs::write(&image, (0..=255u8).collect::<Vec<u8>>()).unwrap();
+	fs::write(&wrong_image, (0..=255u8).rev().collect::<Vec<u8>>()).unwrap();
+
+	let stdout = metastego(&["encode", payload.to_str().unwrap(), packaged.to_str().unwrap(), image.to_str().unwrap(), "--archive", "zip"]);
+	assert!(stdout.starts_with("Successfully encoded"), "{}", stdout);
+	assert!(fs::read(&packaged).unwrap().starts_with(b"PK"));
+	let stdout = metastego(&["decode", packaged.to_str().unwrap(), decoded.to_str().unwrap(), image.to_str().unwrap(), "--archive"]);
+	assert!(stdout.starts_with("Successfully decoded"), "{}", stdout);
+	assert_eq!(fs::read(&decoded).unwrap(), b"a packaged payload");
+	// The metadata's image hash catches the wrong image, even without a checksum.
+	let stdout = metastego(&["decode", packaged.to_str().unwrap(), decoded.to_str().unwrap(), wrong_image.to_str().unwrap(), "--archive"]);
+	assert!(stdout.contains("does not match the fingerprint"), "{}", stdout);
+	fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn profile_writes_a_chrome_trace() {
+	let dir = scratch("profile");
+	let payload = dir.join("payload.bin");
+	let image = dir.join("image.bin");
+	let output = dir.join("output.bin");
+	let trace = dir.join("trace.json");
+	fs::write(&payload, b"a profiled payload").unwrap();
+	fs::write(&image, (0..=255u8).collect::<Vec<u8>>()).unwrap();
+
+	let stdout = metastego(&["encode", payload.to_str().unwrap(), output.to_str().unwrap(), image.to_str().unwrap(), "--profile", trace.to_str().unwrap()]);
+	if cfg!(feature = "profile") {
+		assert!(stdout.starts_with("Successfully encoded"), "{}", stdout);
+		let trace = fs::read_to_string(&trace).unwrap();
+		for phase in ["read", "oracle", "encode", "serialize", "write"] {
+			assert!(trace.contains(&format!("\"name\":\"{}\"", phase)), "no {} span in {}", phase, trace);
+		}
+	} else {
+		// The default build refuses the flag rather than silently writing nothing.
+		assert!(stdout.contains("profile feature"), "{}", stdout);
+		assert!(!output.exists() && !trace.exists());
+	}
+	fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn transcoded_containers_decode_with_the_new_image() {
+	let dir = scratch("transcode");
+	let payload = dir.join("payload.bin");
+	let old_image = dir.join("old.bin");
+	let new_image = dir.join("new.bin");
+	let container = dir.join("encoded.bin");
+	let transcoded = dir.join("transcoded.bin");
+	let decoded = dir.join("decoded.bin");
+	fs::write(&payload, b"a payload that changes covers").unwrap();
+	fs::write(&old_image, (0..=255u8).collect::<Vec<u8>>()).unwrap();
+	fs::write(&new_image, (0..=255u8).rev().collect::<Vec<u8>>()).unwrap();
+
+	metastego(&["encode", payload.to_str().unwrap(), container.to_str().unwrap(), old_image.to_str().unwrap(), "--checksum", "--width", "1"]);
+	let stdout = metastego(&["transcode-image", container.to_str().unwrap(), old_image.to_str().unwrap(), new_image.to_str().unwrap(), transcoded.to_str().unwrap()]);
+	assert!(stdout.starts_with("Successfully transcoded"), "{}", stdout);
+	let stdout = metastego(&["decode", transcoded.to_str().unwrap(), decoded.to_str().unwrap(), new_image.to_str().unwrap()]);
+	assert!(stdout.starts_with("Successfully decoded"), "{}", stdout);
+	assert_eq!(fs::read(&decoded).unwrap(), b"a payload that changes covers");
+	// The checksum was carried over, so the old image is now the wrong one.
+	let stdout = metastego(&["decode", transcoded.to_str().unwrap(), decoded.to_str().unwrap(), old_image.to_str().unwrap()]);
+	assert!(stdout.starts_with("Failed to decode"), "{}", stdout);
+	fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn stats_counts_escapes_in_compressed_payloads() {
+	let dir = scratch("stats");
+	let image = dir.join("image.bin");
+	let container = dir.join("encoded.bin");
+	fs::write(&image, (0..=255u8).collect::<Vec<u8>>()).unwrap();
+	// Deflate makes the incompressible payload longer and the text much shorter, so neither decodes to one byte per offset.
+	for (name, payload) in [("noise", noise(4096, 7)), ("text", b"the same line of text, over and over\n".repeat(100))] {
+		let payload_path = dir.join(name);
+		fs::write(&payload_path, payload).unwrap();
+		metastego(&["encode", payload_path.to_str().unwrap(), container.to_str().unwrap(), image.to_str().unwrap(), "--compress-payload"]);
+		let stdout = metastego(&["stats", container.to_str().unwrap(), image.to_str().unwrap()]);
+		assert!(stdout.contains("(0 escaped literals)"), "{}: {}", name, stdout);
+	}
+	// An image without 0xff has to escape both of them.
+	let payload_path = dir.join("escaped");
+	fs::write(&payload_path, b"\xffab\xff").unwrap();
+	fs::write(&image, (0..255u8).collect::<Vec<u8>>()).unwrap();
+	metastego(&["encode", payload_path.to_str().unwrap(), container.to_str().unwrap(), image.to_str().unwrap(), "--escape"]);
+	let stdout = metastego(&["stats", container.to_str().unwrap(), image.to_str().unwrap()]);
+	assert!(stdout.contains("Offsets: 6 (2 escaped literals)"), "{}", stdout);
+	fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn teach_needs_one_offset_per_value() {
+	let dir = scratch("teach");
+	let payload = dir.join("payload.bin");
+	let image = dir.join("image.bin");
+	let output = dir.join("output.bin");
+	let mapping = dir.join("mapping.txt");
+	fs::write(&payload, b"hello").unwrap();
+	fs::write(&image, (0..=255u8).cycle().take(4096).collect::<Vec<u8>>()).unwrap();
+
+	let mut args = vec!["encode", payload.to_str().unwrap(), output.to_str().unwrap(), image.to_str().unwrap(), "--teach", mapping.to_str().unwrap()];
+	let stdout = metastego(&args);
+	assert!(stdout.starts_with("Successfully encoded"), "{}", stdout);
+	assert!(fs::read_to_string(&mapping).unwrap().contains("0x68"));
+	fs::remove_file(&output).unwrap();
+	fs::remove_file(&mapping).unwrap();
+	// Either of these would make the mapping disagree with the offsets actually stored.
+	for extra in [vec!["--seed", "seed"], vec!["--monotonic"], vec!["--sliding-window", "1024"]] {
+		args.truncate(6);
+		args.extend(extra);
+		let stdout = metastego(&args);
+		assert!(stdout.contains("--teach can't be combined"), "{}", stdout);
+		assert!(!output.exists() && !mapping.exists());
+	}
+	fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn stats_labels_window_relative_offsets() {
+	let dir = scratch("stats-window");
+	let payload = dir.join("payload.bin");
+	let image = dir.join("image.bin");
+	let container = dir.join("encoded.bin");
+	fs::write(&payload, b"hello".repeat(200)).unwrap();
+	fs::write(&image, (0..=255u8).cycle().take(4096).collect::<Vec<u8>>()).unwrap();
+
+	metastego(&["encode", payload.to_str().unwrap(), container.to_str().unwrap(), image.to_str().unwrap(), "--sliding-window", "512"]);
+	let stdout = metastego(&["stats", container.to_str().unwrap(), image.to_str().unwrap()]);
+	assert!(stdout.contains("Sliding window: 512 bytes"), "{}", stdout);
+	assert!(stdout.lines().any(|line| line.starts_with("Offset range") && line.ends_with("relative to the sliding window")), "{}", stdout);
+	fs::remove_dir_all(&dir).unwrap();
+}
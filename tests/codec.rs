@@ -0,0 +1,121 @@
+use metastego::{Container, EncodeOptions, DecodeOptions, Format, MetastegError, WIDTHS, encode_bytes, encode_optimized, decode_bytes};
+use metastego::codec::{OffsetCodec, Planar, Varint};
+
+mod common;
+use common::{noise, reversed_image};
+
+#[test]
+fn varint_round_trip() {
+	let image = reversed_image(1024);
+	let payload = b"varint offsets are smaller for small images".to_vec();
+	let options = EncodeOptions { format: Format::Varint, ..EncodeOptions::default() };
+	let container = encode_bytes(&payload, &image, &options).unwrap();
+	let fixed = encode_bytes(&payload, &image, &EncodeOptions::default()).unwrap();
+	assert!(container.len() < fixed.len());
+	assert_eq!(decode_bytes(&container, &image, &DecodeOptions::default()).unwrap(), payload);
+}
+
+#[test]
+fn varint_codec_handles_large_offsets() {
+	let offsets = vec![0, 127, 128, 16384, u32::MAX];
+	let serialized = Varint.serialize_to_vec(&offsets).unwrap();
+	assert_eq!(Varint.deserialize(&serialized).unwrap(), offsets);
+}
+
+#[test]
+fn truncated_varint_is_rejected() {
+	let serialized = Varint.serialize_to_vec(&[300]).unwrap();
+	let result = Varint.deserialize(&serialized[..1]);
+	assert!(matches!(result, Err(MetastegError::InvalidOffsets(_))));
+}
+
+#[test]
+fn planar_round_trip_for_all_widths() {
+	let image = reversed_image(1024);
+	let payload : Vec<u8> = (0..=255u8).rev().chain(0..=255u8).collect();
+	for width in WIDTHS {
+		let options = EncodeOptions { width, format: Format::Planar, compress_offsets: true, ..EncodeOptions::default() };
+		let container = encode_bytes(&payload, &image, &options).unwrap();
+		assert_eq!(decode_bytes(&container, &image, &DecodeOptions::default()).unwrap(), payload);
+	}
+	let offsets = vec![0x01020304, 0x05060708, 0x00000a0b];
+	let serialized = Planar(4).serialize_to_vec(&offsets).unwrap();
+	assert_eq!(serialized, vec![0x01, 0x05, 0x00, 0x02, 0x06, 0x00, 0x03, 0x07, 0x0a, 0x04, 0x08, 0x0b]);
+	assert_eq!(Planar(4).deserialize(&serialized).unwrap(), offsets);
+	assert!(matches!(Planar(4).deserialize(&serialized[1..]), Err(MetastegError::InvalidLength { .. })));
+}
+
+// Compare planar and fixed offsets, both deflated, on a dense payload and on text.
+#[test]
+fn planar_compresses_dense_payloads_better_than_fixed() {
+	let image = noise(1 << 16, 3);
+	let compressed_size = |payload: &[u8], width: u8, format: Format| {
+		encode_bytes(payload, &image, &EncodeOptions { width, format, compress_offsets: true, ..EncodeOptions::default() }).unwrap().len()
+	};
+	// Random bytes stand in for an already compressed payload. The text is random words, repeated as words and phrases are in prose.
+	let dense = noise(1 << 16, 9);
+	let words = ["the ", "quick ", "brown ", "fox ", "jumps ", "over ", "lazy ", "dog ", "and ", "a ", "of ", "to ", "in ", "is ", "that ", "it ",
+		"was ", "for ", "on ", "are ", "with ", "as ", "his ", "they ", "be ", "at ", "one ", "have ", "this ", "from ", "or ", "had.\n"];
+	let text : Vec<u8> = noise(20_000, 5).iter().flat_map(|x| words[*x as usize % words.len()].bytes()).collect();
+	for width in [4, 8] {
+		// Planar keeps the mostly-zero high bytes together, where deflate squeezes them to almost nothing.
+		let (fixed, planar) = (compressed_size(&dense, width, Format::Fixed), compressed_size(&dense, width, Format::Planar));
+		assert!(planar * 10 < fixed * 9, "width {}: planar {} vs fixed {} on a dense payload", width, planar, fixed);
+		// On text, deflate does better matching whole repeated offsets, which planar splits across the planes.
+		let (fixed, planar) = (compressed_size(&text, width, Format::Fixed), compressed_size(&text, width, Format::Planar));
+		assert!(fixed * 10 < planar * 9, "width {}: fixed {} vs planar {} on text", width, fixed, planar);
+	}
+}
+
+#[test]
+fn optimize_picks_the_smallest_container() {
+	let image = reversed_image(1024);
+	let payload = b"the same few words, the same few words, the same few words".to_vec();
+	let (chosen, container) = encode_optimized(&payload, &image, &EncodeOptions::default()).unwrap();
+	for width in WIDTHS {
+		let options = EncodeOptions { width, ..EncodeOptions::default() };
+		assert!(container.len() <= encode_bytes(&payload, &image, &options).unwrap().len());
+	}
+	assert_eq!(encode_bytes(&payload, &image, &chosen).unwrap(), container);
+	assert_eq!(decode_bytes(&container, &image, &DecodeOptions::default()).unwrap(), payload);
+}
+
+#[test]
+fn container_parse_handles_every_format() {
+	let image = reversed_image(1024);
+	let payload = b"parsed into a Container".to_vec();
+	for format in [Format::Fixed, Format::Varint, Format::Planar, Format::Dictionary] {
+		let options = EncodeOptions { format, width: 2, ..EncodeOptions::default() };
+		let container = Container::parse(&encode_bytes(&payload, &image, &options).unwrap()).unwrap();
+		assert_eq!(Format::from_flags(container.header.flags), format);
+		assert_eq!(container.offsets.len(), payload.len());
+		assert!(container.offsets.iter().zip(&payload).all(|(offset, byte)| image[*offset as usize] == *byte));
+		assert_eq!(container.decode(&image).unwrap(), payload);
+	}
+}
+
+#[test]
+fn dictionary_shrinks_ascii_text() {
+	let image : Vec<u8> = (0..=255u8).cycle().take(70000).collect();
+	let payload = b"the quick brown fox jumps over the lazy dog, again and again and again".repeat(4);
+	let options = EncodeOptions { format: Format::Dictionary, ..EncodeOptions::default() };
+	let container = encode_bytes(&payload, &image, &options).unwrap();
+	assert_eq!(decode_bytes(&container, &image, &DecodeOptions::default()).unwrap(), payload);
+	// One byte per offset plus a small dictionary, instead of four bytes per offset.
+	let fixed = encode_bytes(&payload, &image, &EncodeOptions::default()).unwrap();
+	assert!(container.len() * 2 < fixed.len(), "{} vs {}", container.len(), fixed.len());
+
+	// Offsets that aren't in the dictionary are rejected.
+	let mut tampered = container.clone();
+	*tampered.last_mut().unwrap() = 0xff;
+	assert!(matches!(decode_bytes(&tampered, &image, &DecodeOptions::default()), Err(MetastegError::InvalidOffsets(_))));
+}
+
+#[test]
+fn dictionary_holds_at_most_256_offsets() {
+	// A seed spreads the same byte over many offsets, more than a dictionary can hold.
+	let image : Vec<u8> = vec![b'a'; 4096].into_iter().chain(0..=255u8).collect();
+	let options = EncodeOptions { format: Format::Dictionary, seed: Some("seed".to_string()), ..EncodeOptions::default() };
+	let e = encode_bytes(&vec![b'a'; 2000], &image, &options).unwrap_err();
+	assert!(matches!(e, MetastegError::UnsupportedFeature(_)), "{}", e);
+}
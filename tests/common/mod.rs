@@ -0,0 +1,25 @@
+// Images shared by the integration tests. Each test binary only uses some of them.
+#![allow(dead_code)]
+
+// Every byte value in ascending order, repeated to fill length bytes, so the first occurrence of each value is at its own offset.
+pub fn cycled_image(length: usize) -> Vec<u8> {
+	(0..=255u8).cycle().take(length).collect()
+}
+
+// Every byte value in descending order, repeated to fill length bytes, so offsets and the values they encode differ.
+pub fn reversed_image(length: usize) -> Vec<u8> {
+	(0..=255u8).rev().cycle().take(length).collect()
+}
+
+// A pseudo-random image from a multiplicative hash of each position, so each value has offsets with a variety of low bytes.
+pub fn hashed_image(length: u32) -> Vec<u8> {
+	(0..length).map(|x| (x.wrapping_mul(2654435761) >> 24) as u8).collect()
+}
+
+// A reproducible stream of bytes that looks random, from a linear congruential generator started at state.
+pub fn noise(length: usize, mut state: u32) -> Vec<u8> {
+	(0..length).map(|_| {
+		state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+		(state >> 24) as u8
+	}).collect()
+}
@@ -0,0 +1,44 @@
+use metastego::{EncodeOptions, encode_bytes, extension_for};
+use metastego::header::parse_header;
+
+mod common;
+use common::cycled_image;
+
+#[test]
+fn content_type_is_recorded_or_sniffed() {
+	let image = cycled_image(512);
+	let png = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR".to_vec();
+	let options = EncodeOptions { sniff_content_type: true, ..EncodeOptions::default() };
+	let (header, _) = parse_header(&encode_bytes(&png, &image, &options).unwrap()).unwrap();
+	assert_eq!(header.content_type.as_deref(), Some("image/png"));
+
+	// Unrecognised payloads are left without a content type, and an explicit one wins over sniffing.
+	let (header, _) = parse_header(&encode_bytes(b"plain", &image, &options).unwrap()).unwrap();
+	assert_eq!(header.content_type, None);
+	let options = EncodeOptions { content_type: Some("text/x-notes".to_string()), ..options };
+	let (header, _) = parse_header(&encode_bytes(&png, &image, &options).unwrap()).unwrap();
+	assert_eq!(header.content_type.as_deref(), Some("text/x-notes"));
+}
+
+#[test]
+fn extension_prefers_the_recorded_type() {
+	let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+	assert_eq!(extension_for(None, png).as_deref(), Some("png"));
+	assert_eq!(extension_for(Some("image/png"), png).as_deref(), Some("png"));
+	// Text can't be sniffed, but a recorded type still gives an extension.
+	assert_eq!(extension_for(Some("text/plain"), b"hello").as_deref(), Some("txt"));
+	assert_eq!(extension_for(None, b"hello"), None);
+	assert_eq!(extension_for(Some("application/x-unknown"), b"hello"), None);
+}
+
+#[test]
+fn oversized_content_type_is_refused() {
+	// A header field's length is two bytes, so anything longer must fail rather than be cut short.
+	let image = cycled_image(512);
+	let options = EncodeOptions { content_type: Some("x".repeat(70_000)), ..EncodeOptions::default() };
+	let error = encode_bytes(b"plain", &image, &options).unwrap_err();
+	assert!(error.to_string().contains("content type is 70000 bytes long"), "{}", error);
+	let options = EncodeOptions { content_type: Some("x".repeat(65535)), ..EncodeOptions::default() };
+	let (header, _) = parse_header(&encode_bytes(b"plain", &image, &options).unwrap()).unwrap();
+	assert_eq!(header.content_type.map(|x| x.len()), Some(65535));
+}
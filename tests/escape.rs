@@ -0,0 +1,37 @@
+use metastego::{MetastegError, EncodeOptions, DecodeOptions, Decoder, encode_bytes, decode_bytes};
+use metastego::header::parse_header;
+
+#[test]
+fn escaped_bytes_round_trip() {
+	// Only lowercase letters and spaces are in the image, so everything else has to be escaped.
+	let image = b"the quick brown fox jumps over the lazy dog".to_vec();
+	let payload = b"Hello, World! 123".to_vec();
+	let options = EncodeOptions { escape: true, ..EncodeOptions::default() };
+	let container = encode_bytes(&payload, &image, &options).unwrap();
+
+	let (header, _) = parse_header(&container).unwrap();
+	assert_eq!(header.sentinel, Some(u32::MAX));
+	assert_eq!(decode_bytes(&container, &image, &DecodeOptions::default()).unwrap(), payload);
+
+	let mut decoder = Decoder::new(image);
+	let mut streamed : Vec<u8> = Vec::new();
+	for byte in &container {
+		streamed.extend(decoder.update(&[*byte]).unwrap());
+	}
+	decoder.finish().unwrap();
+	assert_eq!(streamed, payload);
+}
+
+#[test]
+fn sentinel_collision_is_rejected() {
+	// With 1-byte offsets the sentinel is 255, which this image uses as a real offset.
+	let image : Vec<u8> = (0..=255).collect();
+	let options = EncodeOptions { escape: true, width: 1, ..EncodeOptions::default() };
+	let e = encode_bytes(b"x", &image, &options).unwrap_err();
+	assert!(matches!(e, MetastegError::SentinelCollision(255)), "{}", e);
+
+	// Without the last byte value, offset 255 is never used and the sentinel is safe.
+	let options = EncodeOptions { escape: true, width: 1, ..EncodeOptions::default() };
+	let container = encode_bytes(&[0xff, 0x00], &image[..255], &options).unwrap();
+	assert_eq!(decode_bytes(&container, &image[..255], &DecodeOptions::default()).unwrap(), vec![0xff, 0x00]);
+}
@@ -0,0 +1,31 @@
+use metastego::{MetastegError, EncodeOptions, DecodeOptions, encode_bytes, decode_bytes};
+use metastego::header::{CRITICAL_FLAGS, KNOWN_FLAGS};
+
+mod common;
+use common::reversed_image;
+
+// Set extra flags in a container with no disguise, where the flags are bytes 6 to 10.
+fn with_flags(container: &[u8], flags: u32) -> Vec<u8> {
+	let mut container = container.to_vec();
+	let existing = u32::from_be_bytes(container[6..10].try_into().unwrap());
+	container[6..10].copy_from_slice(&(existing | flags).to_be_bytes());
+	container
+}
+
+#[test]
+fn unknown_critical_flag_is_rejected() {
+	let container = encode_bytes(b"payload", &reversed_image(256), &EncodeOptions::default()).unwrap();
+	// The lowest critical flag that isn't defined yet.
+	let unknown = CRITICAL_FLAGS & !KNOWN_FLAGS;
+	let future = unknown & unknown.wrapping_neg();
+	let e = decode_bytes(&with_flags(&container, future), &reversed_image(256), &DecodeOptions::default()).unwrap_err();
+	assert!(matches!(e, MetastegError::UnsupportedFeature(_)), "{}", e);
+}
+
+#[test]
+fn unknown_non_critical_flag_is_ignored() {
+	let container = encode_bytes(b"payload", &reversed_image(256), &EncodeOptions::default()).unwrap();
+	let future = 1 << 31;
+	assert_eq!(future & CRITICAL_FLAGS, 0);
+	assert_eq!(decode_bytes(&with_flags(&container, future), &reversed_image(256), &DecodeOptions::default()).unwrap(), b"payload");
+}
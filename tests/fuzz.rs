@@ -0,0 +1,147 @@
+// Adversarial containers: random mutations of valid headers, huge declared lengths and tiny bodies.
+// Parsing and decoding them must fail cleanly, without panicking or making allocations out of proportion to the input.
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use metastego::{EncodeOptions, DecodeOptions, Container, Decoder, Format, encode_bytes, decode_bytes};
+use metastego::archive;
+
+mod common;
+use common::cycled_image;
+
+// Records the largest single allocation, so the test can tell whether anything was sized from a declared length.
+struct Tracking;
+
+static LARGEST : AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Tracking {
+	unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+		LARGEST.fetch_max(layout.size(), Ordering::Relaxed);
+		unsafe { System.alloc(layout) }
+	}
+
+	unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+		unsafe { System.dealloc(ptr, layout) }
+	}
+
+	unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+		LARGEST.fetch_max(new_size, Ordering::Relaxed);
+		unsafe { System.realloc(ptr, layout, new_size) }
+	}
+}
+
+#[global_allocator]
+static ALLOCATOR : Tracking = Tracking;
+
+// A small deterministic generator, so failures can be reproduced.
+struct Xorshift(u64);
+
+impl Xorshift {
+	fn next(&mut self) -> u64 {
+		self.0 ^= self.0 << 13;
+		self.0 ^= self.0 >> 7;
+		self.0 ^= self.0 << 17;
+		self.0
+	}
+
+	fn below(&mut self, n: usize) -> usize {
+		(self.next() % n as u64) as usize
+	}
+}
+
+// Valid containers covering the header fields and formats, for the mutations to start from.
+fn seeds() -> Vec<Vec<u8>> {
+	let payload = b"a payload to mangle".to_vec();
+	let image = cycled_image(1024);
+	let options = [
+		EncodeOptions::default(),
+		EncodeOptions { width: 1, max_offset: Some(256), checksum: true, fingerprint: true, ..EncodeOptions::default() },
+		EncodeOptions { format: Format::Varint, compress_offsets: true, image_list: true, ..EncodeOptions::default() },
+		EncodeOptions { format: Format::Planar, width: 2, compress_payload: true, content_type: Some("text/plain".to_string()), ..EncodeOptions::default() },
+		EncodeOptions { escape: true, width: 8, payload_offset: Some(2), ..EncodeOptions::default() }
+	];
+	options.iter().map(|x| encode_bytes(&payload, &image, x).unwrap()).collect()
+}
+
+// Throw every kind of input at the parsers. Errors are fine; panics are not.
+fn exercise(container: &[u8], image: &[u8]) {
+	let _ = Container::parse(container);
+	let _ = decode_bytes(container, image, &DecodeOptions::default());
+	let _ = archive::unpack(container);
+	let mut decoder = Decoder::new(image.to_vec());
+	for chunk in container.chunks(7) {
+		if decoder.update(chunk).is_err() {
+			return;
+		}
+	}
+	let _ = decoder.finish();
+}
+
+fn check_bounded(container: &[u8], image: &[u8]) {
+	LARGEST.store(0, Ordering::Relaxed);
+	exercise(container, image);
+	// Deflate can expand data at most about a thousand times, so nothing honest needs more than this.
+	let bound = 1032 * (container.len() + image.len()) + (1 << 16);
+	let largest = LARGEST.load(Ordering::Relaxed);
+	assert!(largest <= bound, "allocated {} bytes for a {}-byte container", largest, container.len());
+}
+
+#[test]
+fn mutated_containers_fail_cleanly() {
+	let image = cycled_image(1024);
+	let mut rng = Xorshift(0x9e3779b97f4a7c15);
+	for seed in seeds() {
+		for _ in 0..2000 {
+			let mut container = seed.clone();
+			for _ in 0..1 + rng.below(4) {
+				match rng.below(4) {
+					// Flip a byte, most often in the header.
+					0 | 1 => {
+						let position = rng.below(container.len().min(64));
+						container[position] = rng.next() as u8;
+					},
+					// Cut the container short.
+					2 => container.truncate(rng.below(container.len() + 1)),
+					// Insert a field claiming to be as long as possible.
+					_ => {
+						let position = 10.min(container.len());
+						let tag = rng.below(16) as u8;
+						container.splice(position..position, [tag, 0xff, 0xff]);
+					}
+				}
+				if container.is_empty() {
+					break;
+				}
+			}
+			check_bounded(&container, &image);
+		}
+	}
+}
+
+#[test]
+fn huge_declared_lengths_are_not_trusted() {
+	let image = cycled_image(1024);
+	// An archive entry declaring an enormous name and data length, with almost nothing after it.
+	let mut packed = vec![0xff, 0xff];
+	packed.extend_from_slice(b"name");
+	check_bounded(&packed, &image);
+	let mut packed = vec![0, 1, b'a'];
+	packed.extend_from_slice(&u64::MAX.to_be_bytes());
+	check_bounded(&packed, &image);
+	// Header fields whose lengths run past the end of the container.
+	for tag in 0..16u8 {
+		let mut container = b"MSTG\x01\x04\0\0\0\0".to_vec();
+		container.extend_from_slice(&[tag, 0xff, 0xff, 1, 2, 3]);
+		check_bounded(&container, &image);
+	}
+	// Every width and flag combination over a body of a few bytes.
+	for width in 0..=9u8 {
+		for flags in [0u32, 1, 2, 4, 8, 16, 32, 0xffff_ffff] {
+			let mut container = b"MSTG\x01".to_vec();
+			container.push(width);
+			container.extend_from_slice(&flags.to_be_bytes());
+			container.extend_from_slice(&[0, 0xff, 0xff, 0xff]);
+			check_bounded(&container, &image);
+		}
+	}
+}
@@ -0,0 +1,61 @@
+use metastego::{EncodeOptions, DecodeOptions, MetastegError, encode_bytes, decode_bytes, check_images, sha256};
+use metastego::header::{parse_header, serialize_header};
+
+mod common;
+use common::cycled_image;
+
+#[test]
+fn image_list_round_trip() {
+	let image = cycled_image(512);
+	let payload = b"checked against the image list".to_vec();
+	let options = EncodeOptions { image_list: true, ..EncodeOptions::default() };
+	let container = encode_bytes(&payload, &image, &options).unwrap();
+	let (header, _) = parse_header(&container).unwrap();
+	assert_eq!(header.images, Some(vec![(sha256(&image), image.len() as u64)]));
+	assert_eq!(decode_bytes(&container, &image, &DecodeOptions::default()).unwrap(), payload);
+	let result = decode_bytes(&container, &cycled_image(513)[1..], &DecodeOptions::default());
+	assert!(matches!(result, Err(MetastegError::ImageListMismatch { index: 1, count: 1 })));
+}
+
+#[test]
+fn image_list_checks_order() {
+	let first = cycled_image(512);
+	// The same image, starting 7 bytes further on.
+	let second = cycled_image(519).split_off(7);
+	let (mut header, _) = parse_header(&[]).unwrap();
+	header.images = Some(vec![(sha256(&first), first.len() as u64), (sha256(&second), second.len() as u64)]);
+	assert!(check_images(&header, &[&first, &second]).is_ok());
+	let result = check_images(&header, &[&second, &first]);
+	assert!(matches!(result, Err(MetastegError::ImageListMismatch { index: 1, count: 2 })));
+	let result = check_images(&header, &[&first]);
+	assert!(matches!(result, Err(MetastegError::ImageCountMismatch { expected: 2, given: 1 })));
+}
+
+#[test]
+fn strict_offsets_reject_a_larger_image() {
+	let image : Vec<u8> = (0..=255u8).collect();
+	let payload = b"\x00\x01\x02".to_vec();
+	let options = EncodeOptions { record_image_length: true, ..EncodeOptions::default() };
+	let container = encode_bytes(&payload, &image, &options).unwrap();
+	let strict = DecodeOptions { strict_offsets: true, ..DecodeOptions::default() };
+	assert_eq!(decode_bytes(&container, &image, &strict).unwrap(), payload);
+
+	// A superset of the image decodes the same offsets, and is only caught by the strict check.
+	let mut larger = image.clone();
+	larger.extend_from_slice(b"extra");
+	assert_eq!(decode_bytes(&container, &larger, &DecodeOptions::default()).unwrap(), payload);
+	let e = decode_bytes(&container, &larger, &strict).unwrap_err();
+	assert!(matches!(e, MetastegError::ImageLengthMismatch { recorded: 256, given: 261 }), "{}", e);
+
+	// An offset beyond the recorded length is reported, even though the image given contains it.
+	let mut header = parse_header(&container).unwrap().0;
+	header.image_length = Some(2);
+	let mut tampered = serialize_header(&header).unwrap();
+	tampered.extend_from_slice(&container[container.len() - 12..]);
+	let e = decode_bytes(&tampered, &image, &strict).unwrap_err();
+	assert!(matches!(e, MetastegError::OffsetBeyondImage { offset: 2, length: 2 }), "{}", e);
+
+	// Without a recorded length there is nothing to be strict about.
+	let plain = encode_bytes(&payload, &image, &EncodeOptions::default()).unwrap();
+	assert!(matches!(decode_bytes(&plain, &image, &strict), Err(MetastegError::UnsupportedFeature(_))));
+}
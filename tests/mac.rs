@@ -0,0 +1,55 @@
+use metastego::{EncodeOptions, DecodeOptions, Decoder, MetastegError, encode_bytes, decode_bytes, hmac_sha256};
+
+mod common;
+use common::cycled_image;
+
+#[test]
+fn hmac_matches_rfc_4231() {
+	let mac = hmac_sha256(b"Jefe", &[b"what do ya want ", b"for nothing?"]);
+	let expected = "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843";
+	assert_eq!(mac.iter().map(|x| format!("{:02x}", x)).collect::<String>(), expected);
+}
+
+#[test]
+fn tampered_container_fails_authentication() {
+	let image = cycled_image(512);
+	let payload = b"authenticated payload".to_vec();
+	let options = EncodeOptions { mac_key: Some("secret".to_string()), ..EncodeOptions::default() };
+	let mut container = encode_bytes(&payload, &image, &options).unwrap();
+	let decode_options = DecodeOptions { mac_key: Some("secret".to_string()), ..DecodeOptions::default() };
+	assert_eq!(decode_bytes(&container, &image, &decode_options).unwrap(), payload);
+
+	let wrong_key = DecodeOptions { mac_key: Some("guess".to_string()), ..DecodeOptions::default() };
+	assert!(matches!(decode_bytes(&container, &image, &wrong_key), Err(MetastegError::AuthenticationFailed)));
+	assert!(matches!(decode_bytes(&container, &image, &DecodeOptions::default()), Err(MetastegError::MacKeyRequired)));
+
+	// Swap two offsets, which still decodes to a payload of the same length.
+	let last = container.len() - 4;
+	container.swap(last - 1, last + 3);
+	assert!(matches!(decode_bytes(&container, &image, &decode_options), Err(MetastegError::AuthenticationFailed)));
+}
+
+#[test]
+fn unauthenticated_container_is_rejected_when_a_key_is_given() {
+	let image = cycled_image(512);
+	let container = encode_bytes(b"no mac", &image, &EncodeOptions::default()).unwrap();
+	let decode_options = DecodeOptions { mac_key: Some("secret".to_string()), ..DecodeOptions::default() };
+	assert!(matches!(decode_bytes(&container, &image, &decode_options), Err(MetastegError::AuthenticationFailed)));
+}
+
+#[test]
+fn authenticated_containers_are_not_streamed() {
+	let image = cycled_image(512);
+	let mut container = encode_bytes(b"hello world", &image, &EncodeOptions { mac_key: Some("secret".to_string()), ..EncodeOptions::default() }).unwrap();
+	// Swap the offsets of 'l' and 'r', which streaming would decode to "hello wolrd" without noticing.
+	let last = container.len() - 4;
+	container.swap(last - 5, last - 1);
+	let keyed = DecodeOptions { mac_key: Some("secret".to_string()), ..DecodeOptions::default() };
+	for options in [keyed.clone(), DecodeOptions::default()] {
+		let mut decoder = Decoder::with_options(image.clone(), options);
+		assert!(matches!(decoder.update(&container), Err(MetastegError::UnsupportedFeature(_))));
+	}
+	// Nor is a key taken as satisfied by a container without a MAC.
+	let unauthenticated = encode_bytes(b"hello world", &image, &EncodeOptions::default()).unwrap();
+	assert!(Decoder::with_options(image.clone(), keyed).update(&unauthenticated).is_err());
+}
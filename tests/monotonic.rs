@@ -0,0 +1,40 @@
+use metastego::{EncodeOptions, Container, Encoder, MetastegError, encode_bytes, decode_bytes};
+
+fn monotonic() -> EncodeOptions {
+	EncodeOptions { monotonic: true, ..EncodeOptions::default() }
+}
+
+#[test]
+fn monotonic_offsets_strictly_increase() {
+	// Every value occurs many times over, in a different order each time round.
+	let image : Vec<u8> = (0..64 * 256u32).map(|x| (x * 167 + x / 256) as u8).collect();
+	let payload = b"abracadabra, abracadabra";
+	let container = encode_bytes(payload, &image, &monotonic()).unwrap();
+	assert_eq!(decode_bytes(&container, &image, &Default::default()).unwrap(), payload);
+	let offsets = Container::parse(&container).unwrap().offsets;
+	assert!(offsets.windows(2).all(|pair| pair[0] < pair[1]));
+	// The repeated bytes would all share one offset without it.
+	let plain = Container::parse(&encode_bytes(payload, &image, &EncodeOptions::default()).unwrap()).unwrap().offsets;
+	assert!(plain.windows(2).any(|pair| pair[0] >= pair[1]));
+}
+
+#[test]
+fn monotonic_offsets_take_the_earliest_occurrence() {
+	let mut image : Vec<u8> = (0..=255u8).collect();
+	image.extend(b"xaxbxaxb");
+	let container = encode_bytes(b"aab", &image, &monotonic()).unwrap();
+	assert_eq!(Container::parse(&container).unwrap().offsets.into_vec(), vec![97, 257, 259]);
+}
+
+#[test]
+fn monotonic_errors() {
+	let image : Vec<u8> = (0..=255u8).collect();
+	// There is no 'a' after the only 'b'.
+	match encode_bytes(b"ba", &image, &monotonic()) {
+		Err(MetastegError::NoMonotonicOffset { byte: b'a', position: 1, after: 98 }) => (),
+		other => panic!("unexpected result: {:?}", other)
+	}
+	assert!(encode_bytes(b"ab", &image, &EncodeOptions { seed: Some("seed".to_string()), ..monotonic() }).is_err());
+	let mut encoder = Encoder::new(&image, &monotonic()).unwrap();
+	assert!(encoder.update(b"a").is_err());
+}
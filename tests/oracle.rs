@@ -0,0 +1,116 @@
+use metastego::{DecodeOptions, EncodeOptions, MetastegError, build_oracle, decode_bytes, encode_bytes};
+use metastego::header::{parse_header, serialize_header};
+use metastego::oracle::{MissingByte, Offsets, RareByte, count_occurrences, create_oracle_all, create_oracle_partial, create_oracle_sampled, find_missing, find_rare};
+
+mod common;
+use common::hashed_image;
+
+// Serialize an oracle the way anything iterating over it would: value then big-endian offset, in iteration order.
+fn serialize(oracle: impl IntoIterator<Item = (u8, u32)>) -> Vec<u8> {
+	oracle.into_iter().flat_map(|(value, offset)| std::iter::once(value).chain(offset.to_be_bytes())).collect()
+}
+
+#[test]
+fn oracle_iteration_is_reproducible() {
+	let image = hashed_image(8192);
+	let first = build_oracle(&image, &EncodeOptions::default()).unwrap();
+	let second = build_oracle(&image, &EncodeOptions::default()).unwrap();
+	let first = serialize(first);
+	assert_eq!(first, serialize(second));
+	// Values are visited in ascending order, whatever order they first occur in the image.
+	let values : Vec<u8> = first.chunks(5).map(|entry| entry[0]).collect();
+	assert_eq!(values, (0..=255u8).collect::<Vec<u8>>());
+
+	let partial = create_oracle_partial(&create_oracle_all(&image), Some(512));
+	assert_eq!(serialize(partial), serialize(create_oracle_partial(&create_oracle_all(&image), Some(512))));
+}
+
+#[test]
+fn max_offset_picks_from_every_occurrence() {
+	let image = hashed_image(8192);
+	let payload = b"keep the offsets in the first half of the image";
+	let options = EncodeOptions { max_offset: Some(4096), ..EncodeOptions::default() };
+	let container = encode_bytes(payload, &image, &options).unwrap();
+	let (header, length) = parse_header(&container).unwrap();
+	assert_eq!(header.max_offset, Some(4096));
+	assert!(Vec::from(Offsets::deserialize(&container[length..], header.width).unwrap()).iter().all(|offset| *offset < 4096));
+	assert_eq!(decode_bytes(&container, &image, &DecodeOptions::default()).unwrap(), payload);
+
+	// Any occurrence inside the window decodes, not just the first one, so use the last.
+	let all = create_oracle_all(&image);
+	let last : Vec<u32> = payload.iter().map(|byte| *all[byte].iter().rev().find(|offset| **offset < 4096).unwrap()).collect();
+	assert!(payload.iter().zip(&last).any(|(byte, offset)| all[byte][0] != *offset));
+	let mut rebuilt = serialize_header(&header).unwrap();
+	rebuilt.extend(Offsets::from(last.clone()).serialize(header.width).unwrap());
+	assert_eq!(decode_bytes(&rebuilt, &image, &DecodeOptions::default()).unwrap(), payload);
+
+	// An occurrence past the window is refused, even though it points at the right byte.
+	let mut outside = last;
+	outside[0] = *all[&payload[0]].iter().find(|offset| **offset >= 4096).unwrap();
+	let mut rebuilt = serialize_header(&header).unwrap();
+	rebuilt.extend(Offsets::from(outside).serialize(header.width).unwrap());
+	assert!(decode_bytes(&rebuilt, &image, &DecodeOptions::default()).is_err());
+}
+
+#[test]
+fn offsets_validate_and_round_trip() {
+	let offsets = Offsets::from(vec![0, 255, 4096]);
+	assert_eq!(offsets.validate(4097), Ok(()));
+	assert_eq!(offsets.validate(4096), Err(4096));
+	let serialized = offsets.serialize(2).unwrap();
+	assert_eq!(serialized.len(), 6);
+	assert_eq!(Offsets::deserialize(&serialized, 2).unwrap(), offsets);
+	assert_eq!(Vec::from(offsets), vec![0, 255, 4096]);
+}
+
+#[test]
+fn missing_bytes_are_all_reported() {
+	let image : Vec<u8> = b"abc".to_vec();
+	let oracle = create_oracle_partial(&create_oracle_all(&image), None);
+	let missing = find_missing(b"axbyyc", &oracle);
+	assert_eq!(missing, vec![MissingByte { byte: b'x', count: 1, first: 1 }, MissingByte { byte: b'y', count: 2, first: 3 }]);
+}
+
+#[test]
+fn sampled_oracle_is_bounded() {
+	let image : Vec<u8> = (0..=255u8).cycle().take(256 * 100).collect();
+	// A small xorshift stands in for a seeded generator.
+	let mut state = 0x2545f4914f6cdd1du64;
+	let below = |n: u64| {
+		state ^= state << 13;
+		state ^= state >> 7;
+		state ^= state << 17;
+		state % n
+	};
+	let sampled = create_oracle_sampled(&image, |value, _| value != 0, Some(5), below);
+	assert_eq!(sampled.len(), 255);
+	for (value, offsets) in &sampled {
+		assert_eq!(offsets.len(), 5);
+		assert!(offsets.windows(2).all(|pair| pair[0] < pair[1]));
+		assert!(offsets.iter().all(|offset| image[*offset as usize] == *value));
+	}
+	// The sample isn't just the first few occurrences.
+	assert!(sampled.values().any(|offsets| offsets.last().unwrap() > &(256 * 5)));
+	// Without a limit, every allowed occurrence is kept.
+	assert_eq!(create_oracle_sampled(&image, |_, _| true, None, |_| 0), create_oracle_all(&image));
+}
+
+#[test]
+fn rare_bytes_are_all_reported() {
+	// Each value v occurs v + 1 times.
+	let image : Vec<u8> = (0..=255u8).flat_map(|value| std::iter::repeat_n(value, value as usize + 1)).collect();
+	let occurrences = count_occurrences(&image);
+	assert_eq!(find_rare(b"\x05\x01\x01\x09", &occurrences, 4), vec![RareByte { byte: 1, occurrences: 2 }]);
+	assert!(find_rare(b"\x03\x09", &occurrences, 4).is_empty());
+
+	let options = EncodeOptions { min_occurrences: Some(4), ..EncodeOptions::default() };
+	match encode_bytes(b"\x00\x05\x02", &image, &options) {
+		Err(MetastegError::TooFewOccurrences { minimum: 4, rare }) => assert_eq!(rare, vec![RareByte { byte: 0, occurrences: 1 }, RareByte { byte: 2, occurrences: 3 }]),
+		other => panic!("unexpected result: {:?}", other)
+	}
+	assert!(encode_bytes(b"\x03\x05", &image, &options).is_ok());
+	// Only occurrences the options allow count towards the minimum.
+	// 0xff fills the last 256 bytes of the image, so the window only leaves it 3 occurrences.
+	let windowed = EncodeOptions { max_offset: Some(image.len() as u32 - 253), ..options };
+	assert!(matches!(encode_bytes(b"\xff", &image, &windowed), Err(MetastegError::TooFewOccurrences { .. })));
+}
@@ -0,0 +1,28 @@
+use metastego::{EncodeOptions, encode_bytes, sha256};
+use metastego::package::{PackageFormat, metadata, metadata_image_hash, package, unpackage};
+
+#[test]
+fn packages_round_trip() {
+	let image : Vec<u8> = (0..=255u8).collect();
+	let container = encode_bytes(b"packaged", &image, &EncodeOptions::default()).unwrap();
+	let described = metadata(&container, &image).unwrap();
+	assert_eq!(metadata_image_hash(&described).unwrap(), Some(sha256(&image)));
+	assert!(described.contains("\"format\": \"fixed\""));
+	for format in [PackageFormat::Tar, PackageFormat::Zip] {
+		let packaged = package(format, &container, &described).unwrap();
+		assert_eq!(PackageFormat::detect(&packaged), format);
+		assert_eq!(unpackage(&packaged).unwrap(), (container.clone(), Some(described.clone())));
+		// Nothing in a package depends on when it was written.
+		assert_eq!(packaged, package(format, &container, &described).unwrap());
+	}
+}
+
+#[test]
+fn unpackage_needs_a_container() {
+	let mut builder = tar::Builder::new(Vec::new());
+	let mut header = tar::Header::new_ustar();
+	header.set_size(2);
+	builder.append_data(&mut header, "metadata.json", &b"{}"[..]).unwrap();
+	assert!(unpackage(&builder.into_inner().unwrap()).is_err());
+	assert!(unpackage(b"PK\x03\x04 not really a zip").is_err());
+}
@@ -0,0 +1,41 @@
+use metastego::{EncodeOptions, DecodeOptions, encode_bytes, decode_bytes};
+use metastego::permute::{permutation, permute, unpermute};
+use metastego::rng::{RngAlgorithm, SeededRng};
+
+#[test]
+fn permutation_is_a_bijection_for_any_length() {
+	for length in (0..64).chain([1000, 4097]) {
+		let mut sorted = permutation("key", length);
+		sorted.sort();
+		assert_eq!(sorted, (0..length).collect::<Vec<usize>>());
+
+		let offsets : Vec<u32> = (0..length as u32).map(|x| x * 3).collect();
+		assert_eq!(unpermute(&permute(&offsets, "key"), "key"), offsets);
+	}
+}
+
+#[test]
+fn permutation_is_not_the_seed_stream() {
+	// The same shuffle driven by the generator --seed would make from the key.
+	let mut rng = SeededRng::new(RngAlgorithm::ChaCha20, "key");
+	let mut seeded : Vec<usize> = (0..256).collect();
+	for i in (1..256).rev() {
+		let j = rng.below(i as u64 + 1) as usize;
+		seeded.swap(i, j);
+	}
+	assert_ne!(permutation("key", 256), seeded);
+}
+
+#[test]
+fn permuted_container_needs_the_key() {
+	let image : Vec<u8> = (0..=255).collect();
+	let payload = b"a payload long enough to be visibly scrambled".to_vec();
+	let encode_options = EncodeOptions { permute_key: Some("secret".to_string()), ..EncodeOptions::default() };
+	let container = encode_bytes(&payload, &image, &encode_options).unwrap();
+
+	let decode_options = DecodeOptions { permute_key: Some("secret".to_string()), ..DecodeOptions::default() };
+	assert_eq!(decode_bytes(&container, &image, &decode_options).unwrap(), payload);
+	assert!(decode_bytes(&container, &image, &DecodeOptions::default()).is_err());
+	let wrong_key = DecodeOptions { permute_key: Some("wrong".to_string()), ..DecodeOptions::default() };
+	assert_ne!(decode_bytes(&container, &image, &wrong_key).unwrap(), payload);
+}
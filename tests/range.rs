@@ -0,0 +1,25 @@
+use metastego::{MetastegError, EncodeOptions, DecodeOptions, encode_bytes, decode_bytes, sha256};
+use metastego::header::parse_header;
+
+mod common;
+use common::reversed_image;
+
+#[test]
+fn mid_file_slice_round_trips() {
+	let payload = b"HEADER|the body we want|FOOTER".to_vec();
+	let options = EncodeOptions { payload_offset: Some(7), payload_length: Some(16), checksum: true, ..EncodeOptions::default() };
+	let container = encode_bytes(&payload, &reversed_image(256), &options).unwrap();
+
+	let (header, _) = parse_header(&container).unwrap();
+	assert_eq!(header.payload_range, Some((7, 16)));
+	// The checksum only covers the slice that was encoded.
+	assert_eq!(header.checksum, Some(sha256(b"the body we want")));
+	assert_eq!(decode_bytes(&container, &reversed_image(256), &DecodeOptions::default()).unwrap(), b"the body we want");
+}
+
+#[test]
+fn slice_outside_payload_is_rejected() {
+	let options = EncodeOptions { payload_offset: Some(4), payload_length: Some(8), ..EncodeOptions::default() };
+	let e = encode_bytes(b"too short", &reversed_image(256), &options).unwrap_err();
+	assert!(matches!(e, MetastegError::InvalidPayloadRange { offset: 4, length: 8, payload_len: 9 }), "{}", e);
+}
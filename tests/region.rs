@@ -0,0 +1,40 @@
+use metastego::{EncodeOptions, DecodeOptions, MetastegError, encode_bytes, decode_bytes, parse_offsets};
+use metastego::header::{parse_header, serialize_header};
+use metastego::region::Region;
+
+mod common;
+use common::cycled_image;
+
+#[test]
+fn bytes_are_encoded_from_their_region() {
+	let image = cycled_image(2048);
+	let payload = b"Region \xff constrained".to_vec();
+	let regions = vec![Region::parse("00-7f:1024-1536").unwrap(), Region::parse("ff:512-1024").unwrap()];
+	let options = EncodeOptions { regions: regions.clone(), ..EncodeOptions::default() };
+	let container = encode_bytes(&payload, &image, &options).unwrap();
+	let (header, offsets) = parse_offsets(&container, &DecodeOptions::default()).unwrap();
+	assert_eq!(header.regions, regions);
+	for (byte, offset) in payload.iter().zip(&offsets) {
+		match byte {
+			0x00..=0x7f => assert!((1024..1536).contains(offset)),
+			_ => assert!((512..1024).contains(offset))
+		}
+	}
+	assert_eq!(decode_bytes(&container, &image, &DecodeOptions::default()).unwrap(), payload);
+}
+
+#[test]
+fn region_violations_are_rejected() {
+	let image = cycled_image(2048);
+	let options = EncodeOptions { regions: vec![Region::parse("41:0-10").unwrap()], ..EncodeOptions::default() };
+	assert!(matches!(encode_bytes(b"A", &image, &options), Err(MetastegError::NoOffsetInRegion(0x41))));
+
+	// An unconstrained container, relabelled with a region it doesn't respect, fails to decode.
+	let container = encode_bytes(b"A", &image, &EncodeOptions::default()).unwrap();
+	let (mut header, body_start) = parse_header(&container).unwrap();
+	header.regions = vec![Region::parse("41:1000-2000").unwrap()];
+	let mut relabelled = serialize_header(&header).unwrap();
+	relabelled.extend_from_slice(&container[body_start..]);
+	let result = decode_bytes(&relabelled, &image, &DecodeOptions::default());
+	assert!(matches!(result, Err(MetastegError::OffsetOutsideRegion { offset: 0x41, byte: 0x41 })));
+}
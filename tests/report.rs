@@ -0,0 +1,16 @@
+use metastego::{EncodeOptions, DecodeOptions, EncodeReport, DecodeReport, encode_with_report, decode_with_report};
+
+#[test]
+fn reports_describe_the_container() {
+	// Every value but 0xff, twice over.
+	let image : Vec<u8> = (0..255u8).chain(0..255u8).collect();
+	let payload = b"abcabc\xff".to_vec();
+	let options = EncodeOptions { escape: true, ..EncodeOptions::default() };
+	let (container, report) = encode_with_report(&payload, &image, &options).unwrap();
+	// Six offsets, then a sentinel and a literal for the escaped byte.
+	assert_eq!(report, EncodeReport { payload_len: 7, image_len: Some(510), offsets: 8, container_len: container.len(), coverage: 255, distinct_offsets: 3 });
+
+	let (decoded, report) = decode_with_report(&container, &image, &DecodeOptions::default()).unwrap();
+	assert_eq!(decoded, payload);
+	assert_eq!(report, DecodeReport { container_len: container.len(), image_len: 510, offsets: 8, distinct_offsets: 3, payload_len: 7 });
+}
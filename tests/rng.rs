@@ -0,0 +1,73 @@
+use metastego::{EncodeOptions, Container, encode_bytes, decode_bytes};
+use metastego::rng::{RngAlgorithm, SeededRng};
+
+mod common;
+use common::cycled_image;
+
+fn seeded(seed: &str, rng: RngAlgorithm) -> EncodeOptions {
+	EncodeOptions { seed: Some(seed.to_string()), rng, ..EncodeOptions::default() }
+}
+
+#[test]
+fn seeded_encode_is_reproducible() {
+	// Every byte value four times over.
+	let image = cycled_image(1024);
+	let payload = b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa".to_vec();
+	for rng in [RngAlgorithm::ChaCha20, RngAlgorithm::Pcg] {
+		let container = encode_bytes(&payload, &image, &seeded("seed", rng)).unwrap();
+		assert_eq!(container, encode_bytes(&payload, &image, &seeded("seed", rng)).unwrap());
+		assert_eq!(decode_bytes(&container, &image, &Default::default()).unwrap(), payload);
+		// The repeated byte is spread over all its occurrences rather than always using the first.
+		let offsets = Container::parse(&container).unwrap().offsets;
+		assert!(offsets.iter().all(|offset| *offset as usize % 256 == b'a' as usize));
+		assert!(offsets.iter().any(|offset| *offset != offsets[0]));
+	}
+	assert_ne!(encode_bytes(&payload, &image, &seeded("seed", RngAlgorithm::ChaCha20)).unwrap(), encode_bytes(&payload, &image, &seeded("other", RngAlgorithm::ChaCha20)).unwrap());
+	assert_ne!(encode_bytes(&payload, &image, &seeded("seed", RngAlgorithm::ChaCha20)).unwrap(), encode_bytes(&payload, &image, &seeded("seed", RngAlgorithm::Pcg)).unwrap());
+}
+
+#[test]
+fn generators_are_pinned() {
+	// These values must never change, or seeded containers would stop being reproducible.
+	let draws = |algorithm| {
+		let mut rng = SeededRng::new(algorithm, "metastego");
+		(0..4).map(|_| rng.below(1000)).collect::<Vec<u64>>()
+	};
+	assert_eq!(draws(RngAlgorithm::ChaCha20), vec![355, 833, 806, 276]);
+	assert_eq!(draws(RngAlgorithm::Pcg), vec![722, 921, 661, 261]);
+}
+
+#[test]
+fn derived_generators_are_independent() {
+	let draws = |mut rng: SeededRng| (0..4).map(|_| rng.next_u64()).collect::<Vec<u64>>();
+	// Moving bytes between the seed and the label gives a different generator.
+	assert_ne!(draws(SeededRng::derived(RngAlgorithm::ChaCha20, "ab", b"c")), draws(SeededRng::derived(RngAlgorithm::ChaCha20, "a", b"bc")));
+	assert_ne!(draws(SeededRng::derived(RngAlgorithm::ChaCha20, "seed", b"")), draws(SeededRng::new(RngAlgorithm::ChaCha20, "seed")));
+}
+
+#[test]
+fn seeded_offsets_fit_the_width() {
+	let image = cycled_image(1024);
+	let payload : Vec<u8> = (0..=255u8).cycle().take(2048).collect();
+	let options = EncodeOptions { width: 1, ..seeded("seed", RngAlgorithm::ChaCha20) };
+	let container = encode_bytes(&payload, &image, &options).unwrap();
+	assert_eq!(decode_bytes(&container, &image, &Default::default()).unwrap(), payload);
+}
+
+#[test]
+fn blocks_share_one_offset_per_value() {
+	let image = cycled_image(1024);
+	let payload = vec![b'a'; 64];
+	let options = EncodeOptions { block_size: Some(16), ..seeded("seed", RngAlgorithm::ChaCha20) };
+	let container = encode_bytes(&payload, &image, &options).unwrap();
+	assert_eq!(decode_bytes(&container, &image, &Default::default()).unwrap(), payload);
+	let offsets = Container::parse(&container).unwrap().offsets;
+	let blocks : Vec<&[u32]> = offsets.chunks(16).collect();
+	assert!(blocks.iter().all(|block| block.iter().all(|offset| *offset == block[0])));
+	// With this seed, the blocks pick different occurrences.
+	assert!(blocks.iter().any(|block| block[0] != blocks[0][0]));
+	assert_eq!(container, encode_bytes(&payload, &image, &options).unwrap());
+
+	let unseeded = EncodeOptions { block_size: Some(16), ..EncodeOptions::default() };
+	assert!(encode_bytes(&payload, &image, &unseeded).is_err());
+}
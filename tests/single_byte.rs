@@ -0,0 +1,41 @@
+use metastego::{EncodeOptions, Container, Decoder, MetastegError, encode_bytes, decode_bytes};
+use metastego::oracle::{create_oracle, create_oracle_all, create_oracle_partial};
+use metastego::region::Region;
+
+// The smallest image there is: one byte, so exactly one byte value can be encoded, always as offset 0.
+const IMAGE : [u8;1] = [b'a'];
+
+#[test]
+fn single_byte_image_reports_the_first_missing_value() {
+	assert_eq!(create_oracle(&IMAGE), Err(0));
+	assert_eq!(create_oracle(&[0]), Err(1));
+	for (image, missing) in [(IMAGE, 0), ([0], 1)] {
+		match encode_bytes(&[image[0]; 4], &image, &EncodeOptions::default()) {
+			Err(MetastegError::MissingByteInImage { byte, max_offset: None }) => assert_eq!(byte, missing),
+			other => panic!("expected a missing byte, got {:?}", other)
+		}
+	}
+}
+
+#[test]
+fn single_byte_image_round_trips_its_own_value() {
+	assert_eq!(create_oracle_partial(&create_oracle_all(&IMAGE), None).into_iter().collect::<Vec<(u8, u32)>>(), vec![(b'a', 0)]);
+	// Escapes let the oracle cover only the values the image has. Without any other value in the payload, none are used.
+	let payload = [b'a'; 16];
+	for options in [EncodeOptions { escape: true, ..EncodeOptions::default() }, EncodeOptions { escape: true, seed: Some("seed".to_string()), ..EncodeOptions::default() }] {
+		let container = encode_bytes(&payload, &IMAGE, &options).unwrap();
+		let parsed = Container::parse(&container).unwrap();
+		assert!(parsed.offsets.iter().all(|offset| *offset == 0));
+		assert_eq!(parsed.offsets.len(), payload.len());
+		assert_eq!(decode_bytes(&container, &IMAGE, &Default::default()).unwrap(), payload);
+
+		let mut decoder = Decoder::new(IMAGE.to_vec());
+		assert_eq!(decoder.update(&container).unwrap(), payload);
+		decoder.finish().unwrap();
+	}
+	// Any other value has to be escaped.
+	let options = EncodeOptions { escape: true, ..EncodeOptions::default() };
+	let container = encode_bytes(b"ab", &IMAGE, &options).unwrap();
+	assert_eq!(decode_bytes(&container, &IMAGE, &Default::default()).unwrap(), b"ab");
+	assert!(matches!(encode_bytes(b"ab", &IMAGE, &EncodeOptions { regions: vec![Region { values: b'a'..=b'a', offsets: 0..1 }], ..EncodeOptions::default() }), Err(MetastegError::UnencodableByte(b'b'))));
+}
@@ -0,0 +1,70 @@
+use metastego::{EncodeOptions, DecodeOptions, MetastegError, Container, Encoder, Decoder, encode_bytes, decode_bytes};
+use metastego::sliding::SlidingWindow;
+
+mod common;
+use common::{cycled_image, noise};
+
+fn windowed(size: u32, advance: u32) -> EncodeOptions {
+	EncodeOptions { width: 2, sliding_window: Some(SlidingWindow { size, advance }), ..EncodeOptions::default() }
+}
+
+#[test]
+fn sliding_window_round_trips_with_small_offsets() {
+	// A 1MB image of bytes that look random, so every value turns up in every window.
+	let image = noise(1 << 20, 1);
+	let payload : Vec<u8> = (0..20_000u32).map(|x| (x * 7 % 251) as u8).collect();
+	// The window moves well past the 64KB a 2-byte offset could reach from the start of the image.
+	let options = windowed(8192, 97);
+	let container = encode_bytes(&payload, &image, &options).unwrap();
+	assert_eq!(decode_bytes(&container, &image, &Default::default()).unwrap(), payload);
+	let parsed = Container::parse(&container).unwrap();
+	assert_eq!(parsed.header.sliding_window, options.sliding_window);
+	assert!(parsed.offsets.iter().all(|offset| *offset < 8192));
+
+	// The same container comes out of a stream, and decodes as one.
+	let mut encoder = Encoder::new(&image, &options).unwrap();
+	let mut streamed : Vec<u8> = Vec::new();
+	for chunk in payload.chunks(999) {
+		streamed.extend(encoder.update(chunk).unwrap());
+	}
+	streamed.extend(encoder.finish().unwrap());
+	assert_eq!(streamed, container);
+	let mut decoder = Decoder::new(image.clone());
+	let mut decoded : Vec<u8> = Vec::new();
+	for chunk in container.chunks(777) {
+		decoded.extend(decoder.update(chunk).unwrap());
+	}
+	decoder.finish().unwrap();
+	assert_eq!(decoded, payload);
+}
+
+#[test]
+fn sliding_window_errors() {
+	let image = noise(1 << 20, 1);
+	// A window that's too small to hold every value, or too big for the image.
+	assert!(encode_bytes(&(0..=255u8).collect::<Vec<u8>>(), &image, &windowed(16, 1)).is_err());
+	assert!(encode_bytes(b"payload", &image[..100], &windowed(200, 1)).is_err());
+	assert!(encode_bytes(b"payload", &image, &EncodeOptions { escape: true, ..windowed(8192, 1) }).is_err());
+
+	// Offsets beyond the end of the window are rejected rather than read from outside it.
+	let mut container = encode_bytes(b"payload", &image, &windowed(8192, 1)).unwrap();
+	let last = container.len() - 2;
+	container[last..].copy_from_slice(&8192u16.to_be_bytes());
+	assert!(decode_bytes(&container, &image, &Default::default()).is_err());
+}
+
+#[test]
+fn strict_offsets_place_the_window_in_the_image_given() {
+	// Every 256 byte window of a cycled image holds every value.
+	let image = cycled_image(4096);
+	let payload : Vec<u8> = (0..4000u32).map(|x| (x * 7 % 251) as u8).collect();
+	let options = EncodeOptions { record_image_length: true, ..windowed(256, 1) };
+	let container = encode_bytes(&payload, &image, &options).unwrap();
+	let strict = DecodeOptions { strict_offsets: true, ..DecodeOptions::default() };
+	assert_eq!(decode_bytes(&container, &image, &strict).unwrap(), payload);
+
+	// In a superset of the image the window reaches further, past the end of the image that was recorded.
+	let larger = cycled_image(4096 + 256);
+	let e = decode_bytes(&container, &larger, &strict).unwrap_err();
+	assert!(matches!(e, MetastegError::OffsetBeyondImage { length: 4096, .. }), "{}", e);
+}
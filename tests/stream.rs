@@ -0,0 +1,77 @@
+use metastego::{EncodeOptions, DecodeOptions, Decoder, encode_bytes, encode_reader, decode_bytes};
+use metastego::disguise::Disguise;
+
+// An image containing every byte value, in a scrambled order so offsets don't equal the values they encode.
+fn complete_image() -> Vec<u8> {
+	let mut image : Vec<u8> = (0..=255).rev().collect();
+	image.extend((0..=255).map(|x : u8| x.wrapping_mul(7)));
+	image
+}
+
+#[test]
+fn decoder_one_byte_at_a_time_matches_full_decode() {
+	let image = complete_image();
+	let payload = b"The quick brown fox jumps over the lazy dog".to_vec();
+	let container = encode_bytes(&payload, &image, &EncodeOptions::default()).unwrap();
+
+	let mut decoder = Decoder::new(image.clone());
+	let mut streamed : Vec<u8> = Vec::new();
+	for byte in &container {
+		streamed.extend(decoder.update(&[*byte]).unwrap());
+	}
+	decoder.finish().unwrap();
+
+	assert_eq!(streamed, decode_bytes(&container, &image, &DecodeOptions::default()).unwrap());
+	assert_eq!(streamed, payload);
+}
+
+#[test]
+fn decoder_rejects_partial_offset() {
+	let image = complete_image();
+	let container = encode_bytes(b"abc", &image, &EncodeOptions::default()).unwrap();
+
+	let mut decoder = Decoder::new(image);
+	decoder.update(&container[..container.len() - 1]).unwrap();
+	assert!(decoder.finish().is_err());
+}
+
+
+#[test]
+fn decoder_skips_disguise_one_byte_at_a_time() {
+	let image = complete_image();
+	let payload = b"hidden behind a png".to_vec();
+	let options = EncodeOptions { disguise: Some(Disguise::Png), ..EncodeOptions::default() };
+	let container = encode_bytes(&payload, &image, &options).unwrap();
+	assert!(container.starts_with(Disguise::Png.preamble()));
+
+	let mut decoder = Decoder::new(image);
+	let mut streamed : Vec<u8> = Vec::new();
+	for byte in &container {
+		streamed.extend(decoder.update(&[*byte]).unwrap());
+	}
+	decoder.finish().unwrap();
+	assert_eq!(streamed, payload);
+}
+
+// A reader that hands out a few bytes at a time, like a slow socket.
+struct Trickle<'a>(&'a [u8]);
+
+impl std::io::Read for Trickle<'_> {
+	fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+		let length = buf.len().min(self.0.len()).min(3);
+		buf[..length].copy_from_slice(&self.0[..length]);
+		self.0 = &self.0[length..];
+		Ok(length)
+	}
+}
+
+#[test]
+fn reader_matches_whole_buffer() {
+	let image = complete_image();
+	let payload = b"read from anything that implements Read".to_vec();
+	// Streamable options are encoded as the reader goes, and the rest are buffered first. Either way the container is the same.
+	for options in [EncodeOptions::default(), EncodeOptions { checksum: true, compress_offsets: true, ..EncodeOptions::default() }] {
+		let container = encode_reader(Trickle(&payload), &image, &options).unwrap();
+		assert_eq!(container, encode_bytes(&payload, &image, &options).unwrap());
+	}
+}
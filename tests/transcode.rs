@@ -0,0 +1,38 @@
+use metastego::{EncodeOptions, DecodeOptions, Container, Format, Header, encode_bytes, decode_bytes, encode_options_for, transcode};
+
+// An image with every byte value, in an order that depends on the step.
+fn image(step: u32) -> Vec<u8> {
+	(0..4096u32).map(|x| (x * step + x / 256) as u8).collect()
+}
+
+#[test]
+fn transcoded_containers_decode_with_the_new_image() {
+	let (old_image, new_image) = (image(167), image(89));
+	let payload = b"a payload whose cover is being rotated".repeat(20);
+	let options = EncodeOptions { width: 2, format: Format::Varint, checksum: true, fingerprint: true, compress_payload: true, permute_key: Some("key".to_string()), content_type: Some("text/plain".to_string()), ..EncodeOptions::default() };
+	let container = encode_bytes(&payload, &old_image, &options).unwrap();
+	let keyed = DecodeOptions { permute_key: Some("key".to_string()), ..DecodeOptions::default() };
+
+	let transcoded = transcode(&container, &old_image, &new_image, &keyed).unwrap();
+	assert_eq!(decode_bytes(&transcoded, &new_image, &keyed).unwrap(), payload);
+	// The fingerprint is of the new image, so the old one no longer decodes it.
+	assert!(decode_bytes(&transcoded, &old_image, &keyed).is_err());
+	// Every other setting comes through unchanged, and it's the same as encoding with the new image from the start.
+	let (before, after) = (Container::parse_with(&container, &keyed).unwrap().header, Container::parse_with(&transcoded, &keyed).unwrap().header);
+	assert_ne!(before.fingerprint, after.fingerprint);
+	assert_eq!(Header { fingerprint: None, ..before.clone() }, Header { fingerprint: None, ..after });
+	assert_eq!(encode_options_for(&before, &keyed).unwrap().format, Format::Varint);
+	assert_eq!(transcoded, encode_bytes(&payload, &new_image, &options).unwrap());
+}
+
+#[test]
+fn transcoding_needs_the_keys_and_the_right_image() {
+	let (old_image, new_image) = (image(167), image(89));
+	let permuted = encode_bytes(b"payload", &old_image, &EncodeOptions { permute_key: Some("key".to_string()), ..EncodeOptions::default() }).unwrap();
+	assert!(transcode(&permuted, &old_image, &new_image, &DecodeOptions::default()).is_err());
+	let authenticated = encode_bytes(b"payload", &old_image, &EncodeOptions { mac_key: Some("key".to_string()), ..EncodeOptions::default() }).unwrap();
+	assert!(transcode(&authenticated, &old_image, &new_image, &DecodeOptions::default()).is_err());
+	// With a checksum, decoding with the wrong old image fails instead of carrying garbage over.
+	let checksummed = encode_bytes(b"payload", &old_image, &EncodeOptions { checksum: true, ..EncodeOptions::default() }).unwrap();
+	assert!(transcode(&checksummed, &new_image, &old_image, &DecodeOptions::default()).is_err());
+}
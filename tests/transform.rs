@@ -0,0 +1,38 @@
+use metastego::{MetastegError, EncodeOptions, DecodeOptions, Decoder, encode_bytes, decode_bytes};
+use metastego::header::parse_header;
+use metastego::transform::Transform;
+
+// Pairs of bytes that average (rounding down) to every value in turn.
+fn downsample_image() -> Vec<u8> {
+	(0..=255u8).flat_map(|x| [x, x.saturating_add(1)]).collect()
+}
+
+#[test]
+fn downsampled_image_round_trips() {
+	let image = downsample_image();
+	assert_eq!(Transform::Downsample.apply(&image), (0..=255u8).collect::<Vec<u8>>());
+	let payload = b"offsets into the transformed image".to_vec();
+	let options = EncodeOptions { transform: Some(Transform::Downsample), ..EncodeOptions::default() };
+	let container = encode_bytes(&payload, &image, &options).unwrap();
+	let (header, _) = parse_header(&container).unwrap();
+	assert_eq!(header.transform, Some(Transform::Downsample));
+	assert_eq!(decode_bytes(&container, &image, &DecodeOptions::default()).unwrap(), payload);
+
+	let mut decoder = Decoder::new(image.clone());
+	assert_eq!(decoder.update(&container).unwrap(), payload);
+	decoder.finish().unwrap();
+}
+
+#[test]
+fn transform_must_keep_every_byte_value() {
+	// Every value, but grayscale maps each triple of them to a narrow range of lumas.
+	let image : Vec<u8> = (0..=255u8).collect();
+	let options = EncodeOptions { transform: Some(Transform::Grayscale), ..EncodeOptions::default() };
+	let e = encode_bytes(b"payload", &image, &options).unwrap_err();
+	assert!(matches!(e, MetastegError::MissingByteAfterTransform { transform: "grayscale", .. }), "{}", e);
+}
+
+#[test]
+fn grayscale_uses_luma() {
+	assert_eq!(Transform::Grayscale.apply(&[255, 255, 255, 255, 0, 0, 0, 255, 0, 7]), vec![255, 76, 150]);
+}
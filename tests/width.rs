@@ -0,0 +1,54 @@
+use metastego::{MetastegError, EncodeOptions, DecodeOptions, encode_bytes, decode_bytes};
+
+mod common;
+use common::reversed_image;
+
+fn encode_with_width(payload: &[u8], width: u8) -> Vec<u8> {
+	let options = EncodeOptions { width, ..EncodeOptions::default() };
+	// An image containing every byte value, short enough that every offset fits in a single byte.
+	encode_bytes(payload, &reversed_image(256), &options).unwrap()
+}
+
+#[test]
+fn width_1_truncated_input_is_still_whole_offsets() {
+	let container = encode_with_width(b"truncate me", 1);
+	assert_eq!(decode_bytes(&container, &reversed_image(256), &DecodeOptions::default()).unwrap(), b"truncate me");
+	// With 1-byte offsets every length is valid, so truncation just loses the tail of the payload.
+	let truncated = &container[..container.len() - 3];
+	assert_eq!(decode_bytes(truncated, &reversed_image(256), &DecodeOptions::default()).unwrap(), b"truncate");
+}
+
+#[test]
+fn width_2_truncated_input_is_rejected() {
+	let container = encode_with_width(b"truncate me", 2);
+	assert_eq!(decode_bytes(&container, &reversed_image(256), &DecodeOptions::default()).unwrap(), b"truncate me");
+	let e = decode_bytes(&container[..container.len() - 1], &reversed_image(256), &DecodeOptions::default()).unwrap_err();
+	assert!(matches!(e, MetastegError::InvalidLength { width: 2, remainder: 1, .. }), "{}", e);
+}
+
+#[test]
+fn width_8_truncated_input_is_rejected() {
+	let container = encode_with_width(b"truncate me", 8);
+	assert_eq!(decode_bytes(&container, &reversed_image(256), &DecodeOptions::default()).unwrap(), b"truncate me");
+	let e = decode_bytes(&container[..container.len() - 3], &reversed_image(256), &DecodeOptions::default()).unwrap_err();
+	assert!(matches!(e, MetastegError::InvalidLength { width: 8, remainder: 5, .. }), "{}", e);
+}
+
+#[test]
+fn offsets_too_large_for_width_are_rejected() {
+	let mut image = vec![0u8; 300];
+	image.extend(reversed_image(256));
+	let options = EncodeOptions { width: 1, ..EncodeOptions::default() };
+	let e = encode_bytes(b"x", &image, &options).unwrap_err();
+	assert!(matches!(e, MetastegError::OffsetTooWide { width: 1, .. }), "{}", e);
+}
+
+#[test]
+fn expect_width_rejects_a_different_declared_width() {
+	let container = encode_with_width(b"width", 2);
+	let matching = DecodeOptions { expect_width: Some(2), ..DecodeOptions::default() };
+	assert_eq!(decode_bytes(&container, &reversed_image(256), &matching).unwrap(), b"width");
+	let different = DecodeOptions { expect_width: Some(4), ..DecodeOptions::default() };
+	let e = decode_bytes(&container, &reversed_image(256), &different).unwrap_err();
+	assert!(matches!(e, MetastegError::WidthMismatch { declared: 2, expected: 4 }), "{}", e);
+}